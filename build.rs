@@ -1,3 +1,4 @@
 fn main() {
     tonic_build::compile_protos("proto/oxydoro/oxydoro.proto").unwrap();
+    tonic_build::compile_protos("proto/oxydoro/storage.proto").unwrap();
 }
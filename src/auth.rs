@@ -0,0 +1,291 @@
+use prost::Message;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tonic::metadata::MetadataValue;
+use tonic::{Request, Status};
+use uuid::Uuid;
+
+use crate::oxydoro::storage::{history_entry, ApiToken, HistoryEntry, TokenList};
+use crate::oxydoro::Task;
+use crate::storage::save_message;
+use crate::store::visible_to;
+
+const TOKEN_FILE_NAME: &str = "tokens.pb";
+/// Metadata key carrying the token as `Bearer <token>`
+const AUTHORIZATION: &str = "authorization";
+/// Metadata key the interceptor passes the authenticated user to handlers in
+const USER_KEY: &str = "x-oxydoro-user-bin";
+
+struct CachedTokens {
+    modified: Option<SystemTime>,
+    tokens: Vec<ApiToken>,
+}
+
+/// Access tokens kept in the data directory
+///
+/// The file is read again whenever it changes so that tokens can be managed while the server runs
+pub struct TokenFile {
+    path: PathBuf,
+    cache: Mutex<CachedTokens>,
+}
+
+impl TokenFile {
+    pub fn new(data_dir: &Path) -> TokenFile {
+        TokenFile {
+            path: data_dir.join(TOKEN_FILE_NAME),
+            cache: Mutex::new(CachedTokens {
+                modified: None,
+                tokens: vec![],
+            }),
+        }
+    }
+
+    pub fn load(&self) -> io::Result<Vec<ApiToken>> {
+        match fs::read(&self.path) {
+            Ok(buffer) => TokenList::decode(buffer.as_slice())
+                .map(|list| list.tokens)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn save(&self, tokens: Vec<ApiToken>) -> io::Result<()> {
+        if let Some(data_dir) = self.path.parent() {
+            fs::create_dir_all(data_dir)?;
+        }
+        save_message(&self.path, &TokenList { tokens })
+    }
+
+    /// Issue a new token, only returned here and never stored
+    pub fn add(&self, name: &str) -> io::Result<String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Token name can't be empty",
+            ));
+        }
+        let mut tokens = self.load()?;
+        if tokens.iter().any(|token| token.name == name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Token {} already exists", name),
+            ));
+        }
+        let token = format!(
+            "{}{}",
+            Uuid::new_v4().to_simple(),
+            Uuid::new_v4().to_simple()
+        );
+        tokens.push(ApiToken {
+            name: name.to_owned(),
+            token_hash: hash_token(&token),
+            created: Some(SystemTime::now().into()),
+        });
+        self.save(tokens)?;
+        Ok(token)
+    }
+
+    /// Returns false if there was no token with this name
+    pub fn revoke(&self, name: &str) -> io::Result<bool> {
+        let mut tokens = self.load()?;
+        let original_count = tokens.len();
+        tokens.retain(|token| token.name != name);
+        if tokens.len() == original_count {
+            return Ok(false);
+        }
+        self.save(tokens)?;
+        Ok(true)
+    }
+
+    /// Holders of tokens, sorted by name
+    pub fn users(&self) -> io::Result<Vec<String>> {
+        let mut users: Vec<String> = self.load()?.into_iter().map(|token| token.name).collect();
+        users.sort();
+        users.dedup();
+        Ok(users)
+    }
+
+    /// Name of the holder of the token
+    pub fn authenticate(&self, token: &str) -> Result<String, Status> {
+        let modified = fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let mut cache = self
+            .cache
+            .lock()
+            .map_err(|_| Status::internal("Failed to unlock tokens"))?;
+        if cache.modified.is_none() || cache.modified != modified {
+            cache.tokens = self
+                .load()
+                .map_err(|_| Status::internal("Failed to read tokens"))?;
+            cache.modified = modified;
+        }
+        let token_hash = hash_token(token);
+        cache
+            .tokens
+            .iter()
+            .find(|token| token.token_hash == token_hash)
+            .map(|token| token.name.clone())
+            .ok_or_else(|| Status::unauthenticated("Invalid token"))
+    }
+}
+
+fn hash_token(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+/// Reject requests without a valid bearer token if tokens are given
+///
+/// Handlers learn about the authenticated user through [`Caller::from_request`]
+pub fn interceptor(
+    tokens: Option<Arc<TokenFile>>,
+) -> impl Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static {
+    move |mut request| {
+        // never trust a user set by the client itself
+        request.metadata_mut().remove_bin(USER_KEY);
+        let tokens = match &tokens {
+            Some(tokens) => tokens,
+            None => return Ok(request),
+        };
+        let token = request
+            .metadata()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("Missing bearer token"))?;
+        let user = tokens.authenticate(token)?;
+        request
+            .metadata_mut()
+            .insert_bin(USER_KEY, MetadataValue::from_bytes(user.as_bytes()));
+        Ok(request)
+    }
+}
+
+/// Request as the interceptor passes it on after authenticating `user`
+#[cfg(test)]
+pub fn request_as<T>(user: &str, message: T) -> Request<T> {
+    let mut request = Request::new(message);
+    request
+        .metadata_mut()
+        .insert_bin(USER_KEY, MetadataValue::from_bytes(user.as_bytes()));
+    request
+}
+
+/// User a request was made by
+///
+/// Callers are anonymous if the server does not require authentication
+pub struct Caller {
+    user: Option<String>,
+}
+
+impl Caller {
+    pub fn from_request<T>(request: &Request<T>) -> Caller {
+        let user = request
+            .metadata()
+            .get_bin(USER_KEY)
+            .and_then(|value| value.to_bytes().ok())
+            .and_then(|user| String::from_utf8(user.to_vec()).ok());
+        Caller { user }
+    }
+
+    #[cfg(test)]
+    pub fn user(name: &str) -> Caller {
+        Caller {
+            user: Some(name.to_owned()),
+        }
+    }
+
+    pub fn is_anonymous(&self) -> bool {
+        self.user.is_none()
+    }
+
+    /// Name recorded as creator, empty for anonymous callers
+    pub fn name(&self) -> &str {
+        self.user.as_deref().unwrap_or_default()
+    }
+
+    /// Anonymous callers are allowed to see everything
+    pub fn is_allowed(&self, visible_to: &[String]) -> bool {
+        match &self.user {
+            Some(user) => visible_to.is_empty() || visible_to.contains(user),
+            None => true,
+        }
+    }
+
+    pub fn can_see(&self, task: &Task) -> bool {
+        self.is_allowed(&visible_to(task))
+    }
+
+    /// History of other users is private, history recorded without authentication is shared
+    pub fn can_see_entry(&self, entry: &HistoryEntry) -> bool {
+        let user = match &entry.kind {
+            Some(history_entry::Kind::Session(session)) => &session.user,
+            Some(history_entry::Kind::Completion(completion)) => &completion.user,
+            None => return true,
+        };
+        match &self.user {
+            Some(name) => user.is_empty() || user == name,
+            None => true,
+        }
+    }
+
+    /// Tasks the caller is not allowed to see are reported as missing
+    pub fn check_visible(&self, task: &Task) -> Result<(), Status> {
+        if self.can_see(task) {
+            return Ok(());
+        }
+        let uuid = task
+            .id
+            .as_ref()
+            .map(|id| id.uuid.as_str())
+            .unwrap_or_default();
+        Err(Status::not_found(format!("Task {} not found", uuid)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(authorization: Option<&str>) -> Request<()> {
+        let mut request = Request::new(());
+        if let Some(authorization) = authorization {
+            request
+                .metadata_mut()
+                .insert(AUTHORIZATION, authorization.parse().unwrap());
+        }
+        request
+    }
+
+    #[test]
+    fn interceptor_rejects_missing_and_unknown_tokens() {
+        let data_dir = std::env::temp_dir().join(format!("oxydoro-test-{}", Uuid::new_v4()));
+        let tokens = Arc::new(TokenFile::new(&data_dir));
+        let token = tokens.add("laptop").unwrap();
+        let intercept = interceptor(Some(Arc::clone(&tokens)));
+
+        let code = |authorization| intercept(request(authorization)).unwrap_err().code();
+        assert_eq!(code(None), tonic::Code::Unauthenticated);
+        assert_eq!(code(Some(token.as_str())), tonic::Code::Unauthenticated);
+        assert_eq!(code(Some("Bearer wrong")), tonic::Code::Unauthenticated);
+        let accepted = intercept(request(Some(&format!("Bearer {}", token)))).unwrap();
+        assert_eq!(Caller::from_request(&accepted).name(), "laptop");
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn interceptor_drops_user_set_by_client() {
+        let mut spoofed = request(None);
+        spoofed
+            .metadata_mut()
+            .insert_bin(USER_KEY, MetadataValue::from_bytes(b"someone"));
+        let accepted = interceptor(None)(spoofed).unwrap();
+        assert!(Caller::from_request(&accepted).is_anonymous());
+    }
+}
@@ -1,7 +1,29 @@
-use oxydoro::oxydoro_client::OxydoroClient;
-use oxydoro::{CreateTaskRequest, GetAllTasksRequest, SubscribeToTaskUpdatesRequest};
+mod client_config;
 
+use oxydoro::{
+    get_all_tasks_request, get_stats_request, interruption, move_task_request, pomodoro_session,
+    pomodoro_timer, task, task_event, ClearCompletedRequest, CompleteTaskRequest,
+    CreateProjectRequest, CreateTaskRequest, DeleteProjectRequest, DeleteTaskRequest,
+    GetAllTasksRequest, GetPomodoroRequest, GetStatsRequest, JoinRoomRequest, LeaveRoomRequest,
+    ListProjectsRequest, ListSessionsRequest, ListUsersRequest, LogInterruptionRequest,
+    MoveTaskRequest, PausePomodoroRequest, PomodoroSession, PomodoroTimer, Project, ProjectId,
+    RenameProjectRequest, ResumePomodoroRequest, StartPomodoroRequest, StopPomodoroRequest,
+    SubscribeToTaskUpdatesRequest, SubscribeToTimerRequest, Task, TaskId, TimerEvent,
+    UpdateTaskRequest,
+};
+
+use client_config::ConnectionArgs;
+
+use chrono::{Local, NaiveDate, Offset, TimeZone};
 use clap::Clap;
+use prost_types::{FieldMask, Timestamp};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+/// Number of times a task creation is tried before giving up
+const CREATE_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(1);
 
 pub mod oxydoro {
     tonic::include_proto!("oxydoro");
@@ -10,6 +32,8 @@ pub mod oxydoro {
 #[derive(Clap)]
 #[clap(author = "David Weis <dweis7@gmail.com>")]
 struct Args {
+    #[clap(flatten)]
+    connection: ConnectionArgs,
     #[clap(subcommand)]
     command: SubCommand,
 }
@@ -17,52 +41,916 @@ struct Args {
 #[derive(Clap)]
 enum SubCommand {
     Add(AddParam),
-    Get,
-    AsyncGet,
+    Get(GetParam),
+    AsyncGet(AsyncGetParam),
+    Done(EditParam),
+    Undone(EditParam),
+    Rename(RenameParam),
+    Estimate(EstimateParam),
+    Update(UpdateParam),
+    Delete(EditParam),
+    Move(MoveParam),
+    ClearCompleted,
+    Start(StartParam),
+    Pause(RoomParam),
+    Resume(RoomParam),
+    Stop(RoomParam),
+    Timer(RoomParam),
+    Interrupt(InterruptParam),
+    WatchTimer(WatchTimerParam),
+    Sessions(SessionsParam),
+    Stats(StatsParam),
+    Projects,
+    AddProject(AddProjectParam),
+    RenameProject(RenameProjectParam),
+    DeleteProject(DeleteProjectParam),
+    /// List users tasks can be assigned to
+    Users,
+    /// Share the timer of a room with everyone else in it
+    ///
+    /// Watching the timer of the room and stopping leaves it
+    Join(JoinParam),
+    Leave(JoinParam),
 }
 
 #[derive(Clap)]
 struct AddParam {
     title: String,
+    /// Number of pomodoros the task is expected to take
+    #[clap(long, default_value = "0")]
+    estimate: u32,
+    /// Key to recognize retries of the same request, generated if not set
+    #[clap(long)]
+    request_id: Option<String>,
+    #[clap(long)]
+    notes: Option<String>,
+    /// Due date as YYYY-MM-DD
+    #[clap(long, parse(try_from_str = parse_due))]
+    due: Option<Timestamp>,
+    /// One of none, low, medium or high
+    #[clap(long, parse(try_from_str = parse_priority), default_value = "none")]
+    priority: task::Priority,
+    #[clap(long = "tag")]
+    tags: Vec<String>,
+    /// Id of the project to add the task to
+    #[clap(long)]
+    project: Option<String>,
+    /// Id of the task to add a subtask to
+    #[clap(long)]
+    parent: Option<String>,
+    /// User to assign the task to
+    #[clap(long)]
+    assignee: Option<String>,
+}
+
+/// Only the given fields are changed
+#[derive(Clap)]
+struct UpdateParam {
+    id: String,
+    #[clap(long)]
+    title: Option<String>,
+    #[clap(long)]
+    estimate: Option<u32>,
+    #[clap(long)]
+    notes: Option<String>,
+    /// Remove the notes
+    #[clap(long, conflicts_with = "notes")]
+    no_notes: bool,
+    /// Due date as YYYY-MM-DD
+    #[clap(long, parse(try_from_str = parse_due))]
+    due: Option<Timestamp>,
+    /// Remove the due date
+    #[clap(long, conflicts_with = "due")]
+    no_due: bool,
+    /// One of none, low, medium or high
+    #[clap(long, parse(try_from_str = parse_priority))]
+    priority: Option<task::Priority>,
+    /// Replaces all tags
+    #[clap(long = "tag")]
+    tags: Vec<String>,
+    /// Remove all tags
+    #[clap(long, conflicts_with = "tags")]
+    no_tags: bool,
+    /// Move the task to the end of this project
+    #[clap(long)]
+    project: Option<String>,
+    /// Remove the task from its project
+    #[clap(long, conflicts_with = "project")]
+    no_project: bool,
+    /// Make the task a subtask of this task
+    #[clap(long)]
+    parent: Option<String>,
+    /// Make the task a top level task
+    #[clap(long, conflicts_with = "parent")]
+    no_parent: bool,
+    #[clap(long)]
+    assignee: Option<String>,
+    /// Remove the assignee
+    #[clap(long, conflicts_with = "assignee")]
+    no_assignee: bool,
+    /// Fail if the task was changed since this version
+    #[clap(long, default_value = "0")]
+    if_version: u64,
+}
+
+#[derive(Clap)]
+struct EditParam {
+    id: String,
+    /// Fail if the task was changed since this version
+    #[clap(long, default_value = "0")]
+    if_version: u64,
+}
+
+/// Place task right before or after another task of its project
+#[derive(Clap)]
+struct MoveParam {
+    id: String,
+    #[clap(long, conflicts_with = "after", required_unless = "after")]
+    before: Option<String>,
+    #[clap(long)]
+    after: Option<String>,
+}
+
+#[derive(Clap)]
+struct GetParam {
+    /// Only tasks of this project
+    #[clap(long)]
+    project: Option<String>,
+    /// One of all, open or done
+    #[clap(long, parse(try_from_str = parse_done_filter), default_value = "all")]
+    status: get_all_tasks_request::DoneFilter,
+    /// Only tasks with this tag
+    #[clap(long)]
+    tag: Option<String>,
+    /// Only tasks due before this date, as YYYY-MM-DD
+    #[clap(long, parse(try_from_str = parse_due))]
+    due_before: Option<Timestamp>,
+    /// Only tasks containing this text in the title or notes
+    #[clap(long)]
+    search: Option<String>,
+    /// One of manual, due, priority or title
+    #[clap(long, parse(try_from_str = parse_sort_order), default_value = "manual")]
+    sort: get_all_tasks_request::SortOrder,
+    /// Number of tasks to show at once, all if not set
+    #[clap(long)]
+    page_size: Option<u32>,
+    /// Page to show, printed after the previous page
+    #[clap(long)]
+    page_token: Option<String>,
+    /// Only tasks created by you
+    #[clap(long, conflicts_with = "assigned-to-me")]
+    mine: bool,
+    /// Only tasks assigned to you
+    #[clap(long)]
+    assigned_to_me: bool,
+}
+
+#[derive(Clap)]
+struct AsyncGetParam {
+    /// Only print changes after this revision
+    #[clap(long, default_value = "0")]
+    since_revision: u64,
+    /// Epoch the revision was printed with, a restarted server sends all tasks instead
+    #[clap(long)]
+    epoch: Option<String>,
+    /// Only changes to tasks of this project
+    #[clap(long)]
+    project: Option<String>,
+}
+
+#[derive(Clap)]
+struct AddProjectParam {
+    name: String,
+}
+
+#[derive(Clap)]
+struct RenameProjectParam {
+    id: String,
+    name: String,
+}
+
+#[derive(Clap)]
+struct DeleteProjectParam {
+    id: String,
+    /// Delete tasks of the project as well
+    #[clap(long)]
+    with_tasks: bool,
+}
+
+#[derive(Clap)]
+struct StartParam {
+    id: String,
+    /// Start the timer of this room instead of the server timer
+    #[clap(long)]
+    room: Option<String>,
+}
+
+#[derive(Clap)]
+struct RoomParam {
+    /// Use the timer of this room instead of the server timer
+    #[clap(long)]
+    room: Option<String>,
+}
+
+#[derive(Clap)]
+struct JoinParam {
+    room: String,
+    /// Name shown to others, only needed if the server does not require authentication
+    #[clap(long)]
+    name: Option<String>,
+}
+
+#[derive(Clap)]
+struct InterruptParam {
+    /// Interruption was caused by someone else
+    #[clap(long)]
+    external: bool,
+    #[clap(long)]
+    note: Option<String>,
+    /// Log the interruption for the pomodoro of this room
+    #[clap(long)]
+    room: Option<String>,
+}
+
+#[derive(Clap)]
+struct WatchTimerParam {
+    /// How often to print remaining time in milliseconds
+    #[clap(long, default_value = "1000")]
+    tick_interval: u32,
+    /// Watch the timer of this room instead of the server timer
+    #[clap(long)]
+    room: Option<String>,
+    /// Name the room was joined as, only needed if the server does not require authentication
+    #[clap(long)]
+    name: Option<String>,
+}
+
+#[derive(Clap)]
+struct SessionsParam {
+    /// Only show sessions started in the last number of days
+    #[clap(long)]
+    days: Option<u64>,
+    /// Only show sessions of this task
+    #[clap(long)]
+    task: Option<String>,
+}
+
+#[derive(Clap)]
+struct StatsParam {
+    /// Aggregate by weeks instead of days
+    #[clap(long)]
+    weekly: bool,
+    /// Only count activity from the last number of days
+    #[clap(long)]
+    days: Option<u64>,
+}
+
+#[derive(Clap)]
+struct RenameParam {
+    id: String,
+    title: String,
+    /// Fail if the task was changed since this version
+    #[clap(long, default_value = "0")]
+    if_version: u64,
+}
+
+#[derive(Clap)]
+struct EstimateParam {
+    id: String,
+    pomodoros: u32,
+    /// Fail if the task was changed since this version
+    #[clap(long, default_value = "0")]
+    if_version: u64,
+}
+
+fn parse_due(date: &str) -> Result<Timestamp, String> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|error| error.to_string())?;
+    let midnight = date
+        .and_hms_opt(0, 0, 0)
+        .and_then(|midnight| Local.from_local_datetime(&midnight).earliest())
+        .ok_or_else(|| String::from("Date does not exist in local time zone"))?;
+    Ok(SystemTime::from(midnight).into())
+}
+
+fn parse_priority(priority: &str) -> Result<task::Priority, String> {
+    match priority {
+        "none" => Ok(task::Priority::None),
+        "low" => Ok(task::Priority::Low),
+        "medium" => Ok(task::Priority::Medium),
+        "high" => Ok(task::Priority::High),
+        _ => Err(format!("Unknown priority {}", priority)),
+    }
+}
+
+fn parse_done_filter(status: &str) -> Result<get_all_tasks_request::DoneFilter, String> {
+    match status {
+        "all" => Ok(get_all_tasks_request::DoneFilter::All),
+        "open" => Ok(get_all_tasks_request::DoneFilter::Open),
+        "done" => Ok(get_all_tasks_request::DoneFilter::Done),
+        _ => Err(format!("Unknown status {}", status)),
+    }
+}
+
+fn parse_sort_order(sort: &str) -> Result<get_all_tasks_request::SortOrder, String> {
+    match sort {
+        "manual" => Ok(get_all_tasks_request::SortOrder::Manual),
+        "due" => Ok(get_all_tasks_request::SortOrder::Due),
+        "priority" => Ok(get_all_tasks_request::SortOrder::Priority),
+        "title" => Ok(get_all_tasks_request::SortOrder::Title),
+        _ => Err(format!("Unknown sort order {}", sort)),
+    }
+}
+
+fn print_task(task: &Task) {
+    print_task_at_depth(task, 0);
+}
+
+fn print_task_at_depth(task: &Task, depth: usize) {
+    let indent = "    ".repeat(depth);
+    let id = task
+        .id
+        .as_ref()
+        .map(|id| id.uuid.as_str())
+        .unwrap_or_default();
+    let mark = if task.done { "x" } else { " " };
+    print!(
+        "{}{} v{} [{}] {} ({}/{} pomodoros)",
+        indent,
+        id,
+        task.version,
+        mark,
+        task.title,
+        task.completed_pomodoros,
+        task.estimated_pomodoros
+    );
+    if task.subtask_count > 0 {
+        print!(
+            " {}/{} subtasks done",
+            task.completed_subtask_count, task.subtask_count
+        );
+    }
+    match task.priority() {
+        task::Priority::None => (),
+        task::Priority::Low => print!(" !low"),
+        task::Priority::Medium => print!(" !medium"),
+        task::Priority::High => print!(" !high"),
+    }
+    if task.due.is_some() {
+        print!(" due {}", format_timestamp(&task.due, "%Y-%m-%d"));
+    }
+    for tag in &task.tags {
+        print!(" #{}", tag);
+    }
+    if !task.assignee.is_empty() {
+        print!(" @{}", task.assignee);
+    }
+    println!();
+    if !task.notes.is_empty() {
+        println!("{}    {}", indent, task.notes);
+    }
+}
+
+/// Print subtasks indented below their parents
+fn print_task_tree(tasks: &[Task], parent_id: Option<&TaskId>, depth: usize) {
+    for task in tasks {
+        let is_root = match &task.parent_id {
+            // parent might not be part of the list
+            Some(id) if parent_id.is_none() => {
+                !tasks.iter().any(|task| task.id.as_ref() == Some(id))
+            }
+            id => id.as_ref() == parent_id,
+        };
+        if is_root {
+            print_task_at_depth(task, depth);
+            print_task_tree(tasks, task.id.as_ref(), depth + 1);
+        }
+    }
+}
+
+fn print_project(project: &Project) {
+    let id = project
+        .id
+        .as_ref()
+        .map(|id| id.uuid.as_str())
+        .unwrap_or_default();
+    println!("{} {}", id, project.name);
+}
+
+fn print_timer(timer: &PomodoroTimer) {
+    let status = match timer.status() {
+        pomodoro_timer::Status::Idle => "Idle",
+        pomodoro_timer::Status::Running => "Running",
+        pomodoro_timer::Status::Paused => "Paused",
+        pomodoro_timer::Status::Finished => "Finished",
+    };
+    let phase = match timer.phase() {
+        pomodoro_timer::Phase::Work => "work",
+        pomodoro_timer::Phase::ShortBreak => "short break",
+        pomodoro_timer::Phase::LongBreak => "long break",
+    };
+    let remaining_seconds = timer.remaining_ms / 1000;
+    print!(
+        "{} {} {:02}:{:02} pomodoro {} of {}",
+        status,
+        phase,
+        remaining_seconds / 60,
+        remaining_seconds % 60,
+        timer.cycle_position,
+        timer.cycle_length
+    );
+    match &timer.task_id {
+        Some(task_id) => println!(" {}", task_id.uuid),
+        None => println!(),
+    }
+}
+
+fn format_timestamp(timestamp: &Option<Timestamp>, format: &str) -> String {
+    timestamp
+        .as_ref()
+        .and_then(|timestamp| {
+            Local
+                .timestamp_opt(timestamp.seconds, timestamp.nanos as u32)
+                .single()
+        })
+        .map(|time| time.format(format).to_string())
+        .unwrap_or_default()
+}
+
+fn print_session(session: &PomodoroSession, task_titles: &HashMap<String, String>) {
+    let outcome = match session.outcome() {
+        pomodoro_session::Outcome::Completed => "completed",
+        pomodoro_session::Outcome::Aborted => "aborted",
+        pomodoro_session::Outcome::Interrupted => "interrupted",
+    };
+    let title = session
+        .task_id
+        .as_ref()
+        .and_then(|task_id| task_titles.get(&task_id.uuid))
+        .map(String::as_str)
+        .unwrap_or("<deleted task>");
+    println!(
+        "{} - {} {:<11} {}",
+        format_timestamp(&session.start, "%Y-%m-%d %H:%M"),
+        format_timestamp(&session.end, "%H:%M"),
+        outcome,
+        title
+    );
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Args = Args::parse();
-
-    let mut client = OxydoroClient::connect("http://127.0.0.1:5001").await?;
+    let mut client = args.connection.connection()?.connect().await?;
 
     match args.command {
         SubCommand::Add(params) => {
-            let request = tonic::Request::new(CreateTaskRequest {
+            let mut request = CreateTaskRequest {
                 title: params.title,
-            });
+                estimated_pomodoros: params.estimate,
+                notes: params.notes.unwrap_or_default(),
+                due: params.due,
+                priority: 0,
+                tags: params.tags,
+                project_id: params.project.map(|uuid| ProjectId { uuid }),
+                parent_id: params.parent.map(|uuid| TaskId { uuid }),
+                assignee: params.assignee.unwrap_or_default(),
+                request_id: params
+                    .request_id
+                    .unwrap_or_else(|| Uuid::new_v4().to_string()),
+            };
+            request.set_priority(params.priority);
 
-            let response = client.create_task(request).await?;
+            let mut attempt = 1;
+            let response = loop {
+                match client
+                    .create_task(tonic::Request::new(request.clone()))
+                    .await
+                {
+                    Err(status)
+                        if status.code() == tonic::Code::Unavailable
+                            && attempt < CREATE_ATTEMPTS =>
+                    {
+                        attempt += 1;
+                        tokio::time::delay_for(RETRY_DELAY).await;
+                    }
+                    result => break result?,
+                }
+            };
 
             let task = response.into_inner().task.unwrap();
             println!("Created new task with ID: {}", task.id.unwrap().uuid);
         }
-        SubCommand::Get => {
-            let request = tonic::Request::new(GetAllTasksRequest {});
-            let response = client.get_all_tasks(request).await?;
-            for task in response.into_inner().tasks {
-                println!("{}", task.title);
+        SubCommand::Get(params) => {
+            let mut request = GetAllTasksRequest {
+                project_id: params.project.map(|uuid| ProjectId { uuid }),
+                tag: params.tag.unwrap_or_default(),
+                due_before: params.due_before,
+                query: params.search.unwrap_or_default(),
+                page_size: params.page_size.unwrap_or_default(),
+                page_token: params.page_token.unwrap_or_default(),
+                ..Default::default()
+            };
+            request.set_done(params.status);
+            request.set_sort(params.sort);
+            if params.mine {
+                request.set_owner(get_all_tasks_request::OwnerFilter::CreatedByMe);
+            } else if params.assigned_to_me {
+                request.set_owner(get_all_tasks_request::OwnerFilter::AssignedToMe);
+            }
+            let response = client
+                .get_all_tasks(tonic::Request::new(request))
+                .await?
+                .into_inner();
+            print_task_tree(&response.tasks, None, 0);
+            if !response.next_page_token.is_empty() {
+                println!("More tasks with --page-token {}", response.next_page_token);
             }
         }
-        SubCommand::AsyncGet => {
-            let mut tasks_stream = client
-                .subscribe_to_task_updates(tonic::Request::new(SubscribeToTaskUpdatesRequest {}))
+        SubCommand::AsyncGet(params) => {
+            let tasks_stream = client
+                .subscribe_to_task_updates(tonic::Request::new(SubscribeToTaskUpdatesRequest {
+                    since_revision: params.since_revision,
+                    project_id: params.project.map(|uuid| ProjectId { uuid }),
+                    epoch: params.epoch.clone().unwrap_or_default(),
+                }))
                 .await?;
             let mut tasks_stream = tasks_stream.into_inner();
             println!("Connected to stream");
+            let mut epoch = params.epoch.unwrap_or_default();
             while let Some(task_update) = tasks_stream.message().await? {
-                println!("Tasks");
-                for task in task_update.tasks {
-                    println!("   {}", task.title);
+                if task_update.epoch != epoch {
+                    println!("Epoch {}", task_update.epoch);
+                    epoch = task_update.epoch;
+                }
+                let event = task_update.event.unwrap_or_default();
+                print!("#{} ", event.revision);
+                match event.kind {
+                    Some(task_event::Kind::Snapshot(list)) => {
+                        println!("Tasks");
+                        for task in list.tasks {
+                            print_task(&task);
+                        }
+                    }
+                    Some(task_event::Kind::Created(task)) => {
+                        print!("Created ");
+                        print_task(&task);
+                    }
+                    Some(task_event::Kind::Updated(task)) => {
+                        print!("Updated ");
+                        print_task(&task);
+                    }
+                    Some(task_event::Kind::Deleted(list)) => {
+                        println!("Deleted");
+                        for id in list.ids {
+                            println!("   {}", id.uuid);
+                        }
+                    }
+                    Some(task_event::Kind::Reordered(order)) => {
+                        println!("Reordered");
+                        for id in order.ids {
+                            println!("   {}", id.uuid);
+                        }
+                    }
+                    None => println!(),
+                }
+            }
+        }
+        SubCommand::Done(params) => {
+            let request = tonic::Request::new(CompleteTaskRequest {
+                id: Some(TaskId { uuid: params.id }),
+                done: true,
+                expected_version: params.if_version,
+            });
+            let response = client.complete_task(request).await?;
+            print_task(&response.into_inner().task.unwrap());
+        }
+        SubCommand::Undone(params) => {
+            let request = tonic::Request::new(CompleteTaskRequest {
+                id: Some(TaskId { uuid: params.id }),
+                done: false,
+                expected_version: params.if_version,
+            });
+            let response = client.complete_task(request).await?;
+            print_task(&response.into_inner().task.unwrap());
+        }
+        SubCommand::Rename(params) => {
+            let request = tonic::Request::new(UpdateTaskRequest {
+                id: Some(TaskId { uuid: params.id }),
+                title: params.title,
+                update_mask: Some(FieldMask {
+                    paths: vec![String::from("title")],
+                }),
+                expected_version: params.if_version,
+                ..Default::default()
+            });
+            let response = client.update_task(request).await?;
+            print_task(&response.into_inner().task.unwrap());
+        }
+        SubCommand::Estimate(params) => {
+            let request = tonic::Request::new(UpdateTaskRequest {
+                id: Some(TaskId { uuid: params.id }),
+                estimated_pomodoros: params.pomodoros,
+                update_mask: Some(FieldMask {
+                    paths: vec![String::from("estimated_pomodoros")],
+                }),
+                expected_version: params.if_version,
+                ..Default::default()
+            });
+            let response = client.update_task(request).await?;
+            print_task(&response.into_inner().task.unwrap());
+        }
+        SubCommand::Update(params) => {
+            let mut request = UpdateTaskRequest {
+                id: Some(TaskId { uuid: params.id }),
+                expected_version: params.if_version,
+                ..Default::default()
+            };
+            let mut paths = vec![];
+            if let Some(title) = params.title {
+                request.title = title;
+                paths.push("title");
+            }
+            if let Some(estimate) = params.estimate {
+                request.estimated_pomodoros = estimate;
+                paths.push("estimated_pomodoros");
+            }
+            if params.notes.is_some() || params.no_notes {
+                request.notes = params.notes.unwrap_or_default();
+                paths.push("notes");
+            }
+            if params.due.is_some() || params.no_due {
+                request.due = params.due;
+                paths.push("due");
+            }
+            if let Some(priority) = params.priority {
+                request.set_priority(priority);
+                paths.push("priority");
+            }
+            if !params.tags.is_empty() || params.no_tags {
+                request.tags = params.tags;
+                paths.push("tags");
+            }
+            if params.project.is_some() || params.no_project {
+                request.project_id = params.project.map(|uuid| ProjectId { uuid });
+                paths.push("project_id");
+            }
+            if params.parent.is_some() || params.no_parent {
+                request.parent_id = params.parent.map(|uuid| TaskId { uuid });
+                paths.push("parent_id");
+            }
+            if params.assignee.is_some() || params.no_assignee {
+                request.assignee = params.assignee.unwrap_or_default();
+                paths.push("assignee");
+            }
+            request.update_mask = Some(FieldMask {
+                paths: paths.into_iter().map(String::from).collect(),
+            });
+            let response = client.update_task(tonic::Request::new(request)).await?;
+            print_task(&response.into_inner().task.unwrap());
+        }
+        SubCommand::Delete(params) => {
+            let request = tonic::Request::new(DeleteTaskRequest {
+                id: Some(TaskId { uuid: params.id }),
+                expected_version: params.if_version,
+            });
+            let response = client.delete_task(request).await?.into_inner();
+            println!(
+                "Deleted task {} with {} subtasks",
+                response.task.unwrap().title,
+                response.deleted_subtask_count
+            );
+        }
+        SubCommand::Move(params) => {
+            let position = match (params.before, params.after) {
+                (Some(uuid), _) => move_task_request::Position::Before(TaskId { uuid }),
+                (None, Some(uuid)) => move_task_request::Position::After(TaskId { uuid }),
+                (None, None) => unreachable!("enforced by clap"),
+            };
+            let request = tonic::Request::new(MoveTaskRequest {
+                id: Some(TaskId { uuid: params.id }),
+                position: Some(position),
+            });
+            let response = client.move_task(request).await?;
+            println!("Moved task {}", response.into_inner().task.unwrap().title);
+        }
+        SubCommand::ClearCompleted => {
+            let request = tonic::Request::new(ClearCompletedRequest {});
+            let response = client.clear_completed(request).await?;
+            println!(
+                "Removed {} completed tasks",
+                response.into_inner().removed_count
+            );
+        }
+        SubCommand::Start(params) => {
+            let request = tonic::Request::new(StartPomodoroRequest {
+                task_id: Some(TaskId { uuid: params.id }),
+                room: params.room.unwrap_or_default(),
+            });
+            let response = client.start_pomodoro(request).await?;
+            print_timer(&response.into_inner().timer.unwrap());
+        }
+        SubCommand::Pause(params) => {
+            let request = tonic::Request::new(PausePomodoroRequest {
+                room: params.room.unwrap_or_default(),
+            });
+            let response = client.pause_pomodoro(request).await?;
+            print_timer(&response.into_inner().timer.unwrap());
+        }
+        SubCommand::Resume(params) => {
+            let request = tonic::Request::new(ResumePomodoroRequest {
+                room: params.room.unwrap_or_default(),
+            });
+            let response = client.resume_pomodoro(request).await?;
+            print_timer(&response.into_inner().timer.unwrap());
+        }
+        SubCommand::Stop(params) => {
+            let request = tonic::Request::new(StopPomodoroRequest {
+                room: params.room.unwrap_or_default(),
+            });
+            let response = client.stop_pomodoro(request).await?;
+            print_timer(&response.into_inner().timer.unwrap());
+        }
+        SubCommand::Timer(params) => {
+            let request = tonic::Request::new(GetPomodoroRequest {
+                room: params.room.unwrap_or_default(),
+            });
+            let response = client.get_pomodoro(request).await?;
+            print_timer(&response.into_inner().timer.unwrap());
+        }
+        SubCommand::Interrupt(params) => {
+            let kind = if params.external {
+                interruption::Kind::External
+            } else {
+                interruption::Kind::Internal
+            };
+            let mut request = LogInterruptionRequest {
+                kind: 0,
+                note: params.note.unwrap_or_default(),
+                room: params.room.unwrap_or_default(),
+            };
+            request.set_kind(kind);
+            let session = client
+                .log_interruption(tonic::Request::new(request))
+                .await?
+                .into_inner()
+                .session
+                .unwrap();
+            println!(
+                "Logged interruption, {} so far in this pomodoro",
+                session.interruptions.len()
+            );
+        }
+        SubCommand::WatchTimer(params) => {
+            let timer_stream = client
+                .subscribe_to_timer(tonic::Request::new(SubscribeToTimerRequest {
+                    tick_interval_ms: params.tick_interval,
+                    room: params.room.unwrap_or_default(),
+                    name: params.name.unwrap_or_default(),
+                }))
+                .await?;
+            let mut timer_stream = timer_stream.into_inner();
+            let mut participants = vec![];
+            while let Some(timer_update) = timer_stream.message().await? {
+                if timer_update.participants != participants {
+                    participants = timer_update.participants.clone();
+                    println!("In the room: {}", participants.join(", "));
                 }
+                match timer_update.event() {
+                    TimerEvent::Tick => (),
+                    TimerEvent::Started => println!("Pomodoro started"),
+                    TimerEvent::Paused => println!("Pomodoro paused"),
+                    TimerEvent::Resumed => println!("Pomodoro resumed"),
+                    TimerEvent::Stopped => println!("Pomodoro stopped"),
+                    TimerEvent::Finished => println!("Pomodoro finished"),
+                    TimerEvent::BreakStarted => println!("Break started"),
+                    TimerEvent::Joined | TimerEvent::Left => continue,
+                }
+                print_timer(&timer_update.timer.unwrap());
             }
         }
+        SubCommand::Sessions(params) => {
+            let since = params
+                .days
+                .map(|days| (SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60)).into());
+            let request = tonic::Request::new(ListSessionsRequest {
+                since,
+                until: None,
+                task_id: params.task.map(|uuid| TaskId { uuid }),
+            });
+            let response = client.list_sessions(request).await?;
+            let tasks = client
+                .get_all_tasks(tonic::Request::new(GetAllTasksRequest::default()))
+                .await?
+                .into_inner()
+                .tasks;
+            let task_titles: HashMap<String, String> = tasks
+                .into_iter()
+                .filter_map(|task| Some((task.id?.uuid, task.title)))
+                .collect();
+            for session in response.into_inner().sessions {
+                print_session(&session, &task_titles);
+            }
+        }
+        SubCommand::Stats(params) => {
+            let period = if params.weekly {
+                get_stats_request::Period::Week
+            } else {
+                get_stats_request::Period::Day
+            };
+            let since = params
+                .days
+                .map(|days| (SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60)).into());
+            let mut request = GetStatsRequest {
+                period: 0,
+                utc_offset_minutes: Local::now().offset().fix().local_minus_utc() / 60,
+                since,
+                until: None,
+            };
+            request.set_period(period);
+            let stats = client
+                .get_stats(tonic::Request::new(request))
+                .await?
+                .into_inner();
+            println!(
+                "{:<12}{:>10}{:>10}{:>8}{:>13}{:>10}{:>10}",
+                "Period", "Pomodoros", "Minutes", "Tasks", "Interrupted", "Internal", "External"
+            );
+            for bucket in &stats.buckets {
+                println!(
+                    "{:<12}{:>10}{:>10}{:>8}{:>13}{:>10}{:>10}",
+                    bucket.start_date,
+                    bucket.completed_pomodoros,
+                    bucket.focused_minutes,
+                    bucket.tasks_completed,
+                    bucket.interrupted_pomodoros,
+                    bucket.internal_interruptions,
+                    bucket.external_interruptions
+                );
+            }
+            println!(
+                "Current streak: {} days, longest streak: {} days",
+                stats.current_streak_days, stats.longest_streak_days
+            );
+        }
+        SubCommand::Projects => {
+            let response = client
+                .list_projects(tonic::Request::new(ListProjectsRequest {}))
+                .await?;
+            for project in response.into_inner().projects {
+                print_project(&project);
+            }
+        }
+        SubCommand::AddProject(params) => {
+            let request = tonic::Request::new(CreateProjectRequest { name: params.name });
+            let response = client.create_project(request).await?;
+            let project = response.into_inner().project.unwrap();
+            println!("Created new project with ID: {}", project.id.unwrap().uuid);
+        }
+        SubCommand::RenameProject(params) => {
+            let request = tonic::Request::new(RenameProjectRequest {
+                id: Some(ProjectId { uuid: params.id }),
+                name: params.name,
+            });
+            let response = client.rename_project(request).await?;
+            print_project(&response.into_inner().project.unwrap());
+        }
+        SubCommand::DeleteProject(params) => {
+            let request = tonic::Request::new(DeleteProjectRequest {
+                id: Some(ProjectId { uuid: params.id }),
+                delete_tasks: params.with_tasks,
+            });
+            let response = client.delete_project(request).await?.into_inner();
+            println!(
+                "Deleted project {} with {} tasks",
+                response.project.unwrap().name,
+                response.deleted_task_count
+            );
+        }
+        SubCommand::Users => {
+            let response = client
+                .list_users(tonic::Request::new(ListUsersRequest {}))
+                .await?;
+            for user in response.into_inner().users {
+                println!("{}", user);
+            }
+        }
+        SubCommand::Join(params) => {
+            let request = tonic::Request::new(JoinRoomRequest {
+                room: params.room,
+                name: params.name.unwrap_or_default(),
+            });
+            let response = client.join_room(request).await?.into_inner();
+            println!("In the room: {}", response.participants.join(", "));
+            print_timer(&response.timer.unwrap());
+        }
+        SubCommand::Leave(params) => {
+            let request = tonic::Request::new(LeaveRoomRequest {
+                room: params.room.clone(),
+                name: params.name.unwrap_or_default(),
+            });
+            client.leave_room(request).await?;
+            println!("Left room {}", params.room);
+        }
     }
 
     Ok(())
@@ -0,0 +1,194 @@
+// tonic::Status is the error type of interceptors
+#![allow(clippy::result_large_err)]
+
+use clap::Clap;
+use serde::Deserialize;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+use tonic::Request;
+
+use crate::oxydoro::oxydoro_client::OxydoroClient;
+
+const DEFAULT_SERVER: &str = "http://127.0.0.1:5001";
+/// Environment variable with the address of the server
+const SERVER_VAR: &str = "OXYDORO_SERVER";
+/// Environment variable with the path of the config file
+const CONFIG_VAR: &str = "OXYDORO_CONFIG";
+/// Environment variable with the access token
+const TOKEN_VAR: &str = "OXYDORO_TOKEN";
+
+/// Client settings read from a TOML file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    pub server: Option<String>,
+    /// Access token for servers that require one
+    pub token: Option<String>,
+    pub tls: TlsFiles,
+}
+
+impl ClientConfig {
+    /// Load config from `path`, `OXYDORO_CONFIG` or `~/.config/oxydoro/client.toml`
+    ///
+    /// Only the default location is allowed to be missing
+    pub fn load(path: Option<PathBuf>) -> Result<ClientConfig, String> {
+        let path = match path.or_else(|| env::var_os(CONFIG_VAR).map(PathBuf::from)) {
+            Some(path) => path,
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(ClientConfig::default()),
+            },
+        };
+        let content = fs::read_to_string(&path)
+            .map_err(|error| format!("Failed to read {}: {}", path.display(), error))?;
+        toml::from_str(&content)
+            .map_err(|error| format!("Invalid config {}: {}", path.display(), error))
+    }
+}
+
+/// PEM files used to connect over TLS
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsFiles {
+    /// CA the server certificate is signed by, system roots are used if not set
+    pub ca_cert: Option<PathBuf>,
+    /// Client certificate for servers that require one
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    /// Name the server certificate is issued for if it differs from the server host
+    pub domain: Option<String>,
+}
+
+impl TlsFiles {
+    fn is_empty(&self) -> bool {
+        self.ca_cert.is_none() && self.cert.is_none() && self.key.is_none() && self.domain.is_none()
+    }
+
+    fn load(&self) -> Result<ClientTlsConfig, String> {
+        let mut tls_config = ClientTlsConfig::new();
+        if let Some(ca_cert) = &self.ca_cert {
+            tls_config = tls_config.ca_certificate(Certificate::from_pem(read_pem(ca_cert)?));
+        }
+        match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => {
+                let identity = Identity::from_pem(read_pem(cert)?, read_pem(key)?);
+                tls_config = tls_config.identity(identity);
+            }
+            (None, None) => (),
+            _ => {
+                return Err(String::from(
+                    "Client certificate requires both a certificate and a key",
+                ))
+            }
+        }
+        if let Some(domain) = &self.domain {
+            tls_config = tls_config.domain_name(domain.clone());
+        }
+        Ok(tls_config)
+    }
+}
+
+/// Options shared by all clients for picking the server to connect to
+///
+/// Options given here take precedence over the environment and the config file
+#[derive(Clap)]
+pub struct ConnectionArgs {
+    /// Address of the server, overrides OXYDORO_SERVER
+    #[clap(long)]
+    server: Option<String>,
+    /// TOML config file, defaults to OXYDORO_CONFIG or ~/.config/oxydoro/client.toml
+    #[clap(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// PEM CA certificate the server certificate is signed by, enables TLS
+    #[clap(long, parse(from_os_str))]
+    ca_cert: Option<PathBuf>,
+    /// PEM client certificate for servers that require one
+    #[clap(long, parse(from_os_str))]
+    client_cert: Option<PathBuf>,
+    /// PEM private key of the client certificate
+    #[clap(long, parse(from_os_str))]
+    client_key: Option<PathBuf>,
+    /// Name the server certificate is issued for if it differs from the server host
+    #[clap(long)]
+    tls_domain: Option<String>,
+    /// Access token for servers that require one, overrides OXYDORO_TOKEN
+    #[clap(long)]
+    token: Option<String>,
+}
+
+impl ConnectionArgs {
+    /// TLS is used for https addresses or if any TLS option is set
+    pub fn connection(self) -> Result<Connection, Box<dyn Error>> {
+        let config = ClientConfig::load(self.config)?;
+        let server = self
+            .server
+            .or_else(|| env::var(SERVER_VAR).ok())
+            .or(config.server)
+            .unwrap_or_else(|| String::from(DEFAULT_SERVER));
+        let tls_files = TlsFiles {
+            ca_cert: self.ca_cert.or(config.tls.ca_cert),
+            cert: self.client_cert.or(config.tls.cert),
+            key: self.client_key.or(config.tls.key),
+            domain: self.tls_domain.or(config.tls.domain),
+        };
+        let mut endpoint = Endpoint::from_shared(server.clone())
+            .map_err(|error| format!("Invalid server address {}: {}", server, error))?;
+        if server.starts_with("https://") || !tls_files.is_empty() {
+            endpoint = endpoint.tls_config(tls_files.load()?)?;
+        }
+        let token = match self
+            .token
+            .or_else(|| env::var(TOKEN_VAR).ok())
+            .or(config.token)
+        {
+            Some(token) => Some(
+                MetadataValue::from_str(&format!("Bearer {}", token))
+                    .map_err(|_| "Token contains invalid characters")?,
+            ),
+            None => None,
+        };
+        Ok(Connection { endpoint, token })
+    }
+}
+
+/// Everything needed to connect to the server
+#[derive(Debug, Clone)]
+pub struct Connection {
+    endpoint: Endpoint,
+    /// Value of the authorization header sent with every request
+    token: Option<MetadataValue<Ascii>>,
+}
+
+impl Connection {
+    pub async fn connect(self) -> Result<OxydoroClient<Channel>, tonic::transport::Error> {
+        let channel = self.endpoint.connect().await?;
+        let token = self.token;
+        Ok(OxydoroClient::with_interceptor(
+            channel,
+            move |mut request: Request<()>| {
+                if let Some(token) = &token {
+                    request
+                        .metadata_mut()
+                        .insert("authorization", token.clone());
+                }
+                Ok(request)
+            },
+        ))
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(config_dir) => PathBuf::from(config_dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("oxydoro").join("client.toml"))
+}
+
+fn read_pem(path: &Path) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|error| format!("Failed to read {}: {}", path.display(), error))
+}
@@ -0,0 +1,107 @@
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+/// Server settings read from a TOML file
+///
+/// Everything is optional, command line arguments take precedence over the file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen: Option<SocketAddr>,
+    pub data_dir: Option<PathBuf>,
+    /// Keep tasks in memory only instead of in the data directory
+    pub in_memory: bool,
+    pub work_minutes: Option<u64>,
+    pub short_break_minutes: Option<u64>,
+    pub long_break_minutes: Option<u64>,
+    pub long_break_interval: Option<u32>,
+    /// Reject requests without a valid access token
+    pub require_auth: bool,
+    pub limits: Limits,
+    pub tls: TlsFiles,
+}
+
+impl ServerConfig {
+    pub fn load(path: &Path) -> Result<ServerConfig, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|error| format!("Failed to read {}: {}", path.display(), error))?;
+        toml::from_str(&content)
+            .map_err(|error| format!("Invalid config {}: {}", path.display(), error))
+    }
+}
+
+/// Data directory used unless one is configured, none if there is no home directory
+pub fn default_data_dir() -> Option<PathBuf> {
+    let data_dir = match env::var_os("XDG_DATA_HOME").filter(|data_dir| !data_dir.is_empty()) {
+        Some(data_dir) => PathBuf::from(data_dir),
+        None => PathBuf::from(env::var_os("HOME")?)
+            .join(".local")
+            .join("share"),
+    };
+    Some(data_dir.join("oxydoro"))
+}
+
+/// PEM files of the server, connections are only encrypted if certificate and key are given
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsFiles {
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    /// CA that client certificates are checked against, clients need no certificate if not set
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsFiles {
+    pub fn load(&self) -> Result<Option<ServerTlsConfig>, String> {
+        let (cert, key) = match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => (cert, key),
+            (None, None) if self.client_ca.is_none() => return Ok(None),
+            _ => return Err(String::from("TLS requires both a certificate and a key")),
+        };
+        let identity = Identity::from_pem(read_pem(cert)?, read_pem(key)?);
+        let mut tls_config = ServerTlsConfig::new().identity(identity);
+        if let Some(client_ca) = &self.client_ca {
+            tls_config = tls_config.client_ca_root(Certificate::from_pem(read_pem(client_ca)?));
+        }
+        Ok(Some(tls_config))
+    }
+}
+
+fn read_pem(path: &Path) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|error| format!("Failed to read {}: {}", path.display(), error))
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Number of recent task events kept in memory
+    pub change_log_capacity: usize,
+    /// Number of task events a subscriber can fall behind before it is dropped
+    pub subscriber_buffer: usize,
+    /// How long request ids of created tasks are remembered
+    pub create_request_window_hours: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            change_log_capacity: 1024,
+            subscriber_buffer: 256,
+            create_request_window_hours: 24,
+        }
+    }
+}
+
+impl Limits {
+    /// None if the number of hours is too large to be represented
+    pub fn create_request_window(&self) -> Option<Duration> {
+        self.create_request_window_hours
+            .checked_mul(60 * 60)
+            .map(Duration::from_secs)
+    }
+}
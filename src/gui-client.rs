@@ -1,45 +1,202 @@
+mod client_config;
 mod style;
 
 use iced::{
-    executor, scrollable, text_input, Align, Application, Color, Column, Command, Container,
-    Element, Length, Row, Scrollable, Settings, Subscription, Text, TextInput,
+    executor, scrollable, text_input, Align, Application, Checkbox, Color, Column, Command,
+    Container, Element, Length, Row, Scrollable, Settings, Subscription, Text, TextInput,
 };
 
+use chrono::{Local, TimeZone};
+use clap::Clap;
+use client_config::{Connection, ConnectionArgs};
+use iced_native::input::{keyboard, ButtonState};
 use style::Theme;
 
 use oxydoro::oxydoro_client::OxydoroClient;
 use oxydoro::{
-    CreateTaskRequest, GetAllTasksRequest, SubscribeToTaskUpdatesReply,
-    SubscribeToTaskUpdatesRequest, Task,
+    interruption, move_task_request, pomodoro_timer, task, task_event, CompleteTaskRequest,
+    CreateTaskRequest, GetAllTasksRequest, JoinRoomRequest, LogInterruptionRequest,
+    MoveTaskRequest, PomodoroTimer, SubscribeToTaskUpdatesReply, SubscribeToTaskUpdatesRequest,
+    SubscribeToTimerReply, SubscribeToTimerRequest, Task, TaskEvent, TaskId,
 };
+use std::time::Duration;
 use tonic::transport::Channel;
 use tonic::Streaming;
+use uuid::Uuid;
+
+/// Time to wait before subscribing again after the server could not be reached
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
 
 pub mod oxydoro {
     tonic::include_proto!("oxydoro");
 }
 
+#[derive(Clap)]
+struct Args {
+    #[clap(flatten)]
+    connection: ConnectionArgs,
+    /// Use the light theme
+    #[clap(long)]
+    light: bool,
+    /// Join this room and follow its timer instead of the server timer
+    #[clap(long)]
+    room: Option<String>,
+    /// Name to join the room as, only needed if the server does not require authentication
+    #[clap(long)]
+    name: Option<String>,
+}
+
+struct Flags {
+    theme: Theme,
+    connection: Connection,
+    room: String,
+    name: String,
+}
+
 struct OxydoroUI {
     state: OxydoroState,
     theme: Theme,
+    /// Room whose timer is shown, server timer if empty
+    room: String,
+    /// Name the room is joined as
+    name: String,
 }
 
 struct LoadedViewState {
     rpc_connector: OxydoroClient<Channel>,
     tasks: Vec<Task>,
+    timer: Option<PomodoroTimer>,
+    room: String,
+    /// Everyone in the room, empty for the server timer
+    participants: Vec<String>,
     scroll_state: scrollable::State,
     text_input_state: text_input::State,
     new_task_name: String,
+    /// Task moved around with the keyboard
+    selected: Option<TaskId>,
 }
 
 impl LoadedViewState {
-    fn new(rpc_connector: OxydoroClient<Channel>, task_list: Vec<Task>) -> LoadedViewState {
+    fn new(
+        rpc_connector: OxydoroClient<Channel>,
+        task_list: Vec<Task>,
+        room: String,
+    ) -> LoadedViewState {
         LoadedViewState {
             rpc_connector,
             tasks: task_list,
+            timer: None,
+            room,
+            participants: vec![],
             scroll_state: scrollable::State::new(),
             text_input_state: text_input::State::focused(),
             new_task_name: String::new(),
+            selected: None,
+        }
+    }
+
+    fn selected_index(&self) -> Option<usize> {
+        let selected = self.selected.as_ref()?;
+        self.tasks
+            .iter()
+            .position(|task| task.id.as_ref() == Some(selected))
+    }
+
+    /// Select the task above or below the selected one
+    fn select_neighbour(&mut self, up: bool) {
+        let index = match (self.selected_index(), up) {
+            (None, true) => self.tasks.len().checked_sub(1),
+            (None, false) => Some(0),
+            (Some(index), true) => index.checked_sub(1),
+            (Some(index), false) => Some(index + 1),
+        };
+        if let Some(task) = index.and_then(|index| self.tasks.get(index)) {
+            self.selected = task.id.clone();
+        }
+    }
+
+    /// Ask the server to swap the selected task with its neighbour in the same project
+    fn move_selected(&self, up: bool) -> Command<Message> {
+        let index = match self.selected_index() {
+            Some(index) => index,
+            None => return Command::none(),
+        };
+        let task = &self.tasks[index];
+        let same_project = |other: &&Task| other.project_id == task.project_id;
+        let neighbour = if up {
+            self.tasks[..index].iter().rev().find(same_project)
+        } else {
+            self.tasks[index + 1..].iter().find(same_project)
+        };
+        let target = match neighbour.and_then(|neighbour| neighbour.id.clone()) {
+            Some(target) => target,
+            None => return Command::none(),
+        };
+        let position = if up {
+            move_task_request::Position::Before(target)
+        } else {
+            move_task_request::Position::After(target)
+        };
+        let request = tonic::Request::new(MoveTaskRequest {
+            id: task.id.clone(),
+            position: Some(position),
+        });
+        let mut rpc_connector = self.rpc_connector.clone();
+        let future = async move { rpc_connector.move_task(request).await };
+        Command::perform(future, |_| Message::TaskMoved)
+    }
+
+    fn log_interruption(&self, kind: interruption::Kind) -> Command<Message> {
+        let mut request = LogInterruptionRequest {
+            kind: 0,
+            note: String::new(),
+            room: self.room.clone(),
+        };
+        request.set_kind(kind);
+        let request = tonic::Request::new(request);
+        let mut rpc_connector = self.rpc_connector.clone();
+        let future = async move { rpc_connector.log_interruption(request).await };
+        Command::perform(future, |_| Message::InterruptionLogged)
+    }
+
+    /// Bring local copy of the task list up to date
+    fn apply(&mut self, event: TaskEvent) {
+        match event.kind {
+            Some(task_event::Kind::Snapshot(list)) => self.tasks = list.tasks,
+            Some(task_event::Kind::Created(task)) => self.tasks.push(task),
+            Some(task_event::Kind::Updated(task)) => {
+                if let Some(existing) = self.tasks.iter_mut().find(|other| other.id == task.id) {
+                    *existing = task;
+                }
+            }
+            Some(task_event::Kind::Deleted(list)) => self
+                .tasks
+                .retain(|task| !list.ids.iter().any(|id| task.id.as_ref() == Some(id))),
+            Some(task_event::Kind::Reordered(order)) => {
+                // listed tasks swap places among themselves, others stay where they are
+                let positions: Vec<usize> = self
+                    .tasks
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, task)| order.ids.iter().any(|id| task.id.as_ref() == Some(id)))
+                    .map(|(index, _)| index)
+                    .collect();
+                let mut reordered: Vec<Task> = order
+                    .ids
+                    .iter()
+                    .filter_map(|id| {
+                        self.tasks
+                            .iter()
+                            .find(|task| task.id.as_ref() == Some(id))
+                            .cloned()
+                    })
+                    .collect();
+                reordered.truncate(positions.len());
+                for (index, task) in positions.into_iter().zip(reordered) {
+                    self.tasks[index] = task;
+                }
+            }
+            None => (),
         }
     }
 }
@@ -49,15 +206,17 @@ enum OxydoroState {
     Connected {
         rpc_connector: OxydoroClient<Channel>,
     },
-    LoadedView(LoadedViewState),
+    LoadedView(Box<LoadedViewState>),
     Error,
 }
 
 impl OxydoroUI {
-    fn new(theme: Theme) -> OxydoroUI {
+    fn new(theme: Theme, room: String, name: String) -> OxydoroUI {
         OxydoroUI {
             state: OxydoroState::Connecting,
             theme,
+            room,
+            name,
         }
     }
 }
@@ -68,10 +227,23 @@ enum OxydoroError {
     ConnectionError,
 }
 
-async fn create_rpc_connection(address: String) -> Result<OxydoroClient<Channel>, OxydoroError> {
-    OxydoroClient::connect(address)
+/// Room is joined right away so that its timer can be followed
+async fn create_rpc_connection(
+    connection: Connection,
+    room: String,
+    name: String,
+) -> Result<OxydoroClient<Channel>, OxydoroError> {
+    let mut client = connection
+        .connect()
         .await
-        .map_err(|_| OxydoroError::ConnectionError)
+        .map_err(|_| OxydoroError::ConnectionError)?;
+    if !room.is_empty() {
+        client
+            .join_room(tonic::Request::new(JoinRoomRequest { room, name }))
+            .await
+            .map_err(|_| OxydoroError::ConnectionError)?;
+    }
+    Ok(client)
 }
 
 #[derive(Debug, Clone)]
@@ -81,19 +253,25 @@ enum Message {
     InputChanged(String),
     SubmitNewTask,
     TaskCreated,
+    TaskToggled(TaskId, u64, bool),
+    TaskUpdated,
+    TaskMoved,
     StreamUpdate(SubOutput),
+    TimerUpdate(TimerSubOutput),
+    EventOccurred(iced_native::Event),
+    InterruptionLogged,
 }
 
 impl Application for OxydoroUI {
     type Executor = executor::Default;
     type Message = Message;
-    type Flags = Theme;
+    type Flags = Flags;
 
-    fn new(flags: Theme) -> (OxydoroUI, Command<Message>) {
+    fn new(flags: Flags) -> (OxydoroUI, Command<Message>) {
         (
-            OxydoroUI::new(flags),
+            OxydoroUI::new(flags.theme, flags.room.clone(), flags.name.clone()),
             Command::perform(
-                create_rpc_connection(String::from("http://127.0.0.1:5001")),
+                create_rpc_connection(flags.connection, flags.room, flags.name),
                 Message::Connected,
             ),
         )
@@ -114,7 +292,7 @@ impl Application for OxydoroUI {
                 self.state = OxydoroState::Connected {
                     rpc_connector: rpc_client.clone(),
                 };
-                let request = tonic::Request::new(GetAllTasksRequest {});
+                let request = tonic::Request::new(GetAllTasksRequest::default());
                 let future = async move { rpc_client.get_all_tasks(request).await };
                 Command::perform(future, |response| {
                     Message::Received(
@@ -130,10 +308,11 @@ impl Application for OxydoroUI {
             }
             Message::Received(Ok(task_list)) => {
                 if let OxydoroState::Connected { rpc_connector } = &self.state {
-                    self.state = OxydoroState::LoadedView(LoadedViewState::new(
+                    self.state = OxydoroState::LoadedView(Box::new(LoadedViewState::new(
                         rpc_connector.clone(),
                         task_list,
-                    ));
+                        self.room.clone(),
+                    )));
                 }
                 Command::none()
             }
@@ -152,6 +331,8 @@ impl Application for OxydoroUI {
                 if let OxydoroState::LoadedView(loaded_view_state) = &mut self.state {
                     let request = tonic::Request::new(CreateTaskRequest {
                         title: loaded_view_state.new_task_name.clone(),
+                        request_id: Uuid::new_v4().to_string(),
+                        ..Default::default()
                     });
                     loaded_view_state.new_task_name = String::new();
                     let mut rpc_connector = loaded_view_state.rpc_connector.clone();
@@ -162,24 +343,100 @@ impl Application for OxydoroUI {
                 }
             }
             Message::TaskCreated => Command::none(),
+            Message::TaskToggled(id, version, done) => {
+                if let OxydoroState::LoadedView(loaded_view_state) = &mut self.state {
+                    // rejected if someone else changed the task in the meantime
+                    let request = tonic::Request::new(CompleteTaskRequest {
+                        id: Some(id),
+                        done,
+                        expected_version: version,
+                    });
+                    let mut rpc_connector = loaded_view_state.rpc_connector.clone();
+                    let future = async move { rpc_connector.complete_task(request).await };
+                    Command::perform(future, |_| Message::TaskUpdated)
+                } else {
+                    Command::none()
+                }
+            }
+            Message::TaskUpdated => Command::none(),
+            Message::TaskMoved => Command::none(),
             Message::StreamUpdate(update) => {
                 if let SubOutput::Message(message) = update {
-                    if let OxydoroState::LoadedView(ref mut view) = self.state {
-                        view.tasks = message.tasks;
+                    if let (OxydoroState::LoadedView(ref mut view), Some(event)) =
+                        (&mut self.state, message.event)
+                    {
+                        view.apply(event);
                     }
                 }
                 Command::none()
             }
+            Message::TimerUpdate(update) => {
+                if let OxydoroState::LoadedView(ref mut view) = self.state {
+                    match update {
+                        TimerSubOutput::Message(message) => {
+                            view.timer = message.timer;
+                            view.participants = message.participants;
+                        }
+                        // don't show a stale timer while resubscribing
+                        TimerSubOutput::Error => {
+                            view.timer = None;
+                            view.participants.clear();
+                        }
+                        TimerSubOutput::Loading => {}
+                    }
+                }
+                Command::none()
+            }
+            Message::EventOccurred(iced_native::Event::Keyboard(keyboard::Event::Input {
+                state: ButtonState::Pressed,
+                key_code,
+                modifiers,
+            })) => {
+                let view = match &mut self.state {
+                    OxydoroState::LoadedView(view) => view,
+                    _ => return Command::none(),
+                };
+                // Ctrl+I logs an internal and Ctrl+E an external interruption,
+                // arrows select a task and move it together with Ctrl
+                match (key_code, modifiers.control) {
+                    (keyboard::KeyCode::I, true) => {
+                        view.log_interruption(interruption::Kind::Internal)
+                    }
+                    (keyboard::KeyCode::E, true) => {
+                        view.log_interruption(interruption::Kind::External)
+                    }
+                    (keyboard::KeyCode::Up, true) => view.move_selected(true),
+                    (keyboard::KeyCode::Down, true) => view.move_selected(false),
+                    (keyboard::KeyCode::Up, false) => {
+                        view.select_neighbour(true);
+                        Command::none()
+                    }
+                    (keyboard::KeyCode::Down, false) => {
+                        view.select_neighbour(false);
+                        Command::none()
+                    }
+                    _ => Command::none(),
+                }
+            }
+            Message::EventOccurred(_) => Command::none(),
+            Message::InterruptionLogged => Command::none(),
         }
     }
 
     fn subscription(&self) -> Subscription<Message> {
         match self.state {
             OxydoroState::Connected { ref rpc_connector } => Subscription::none(),
-            OxydoroState::LoadedView(ref state) => {
+            OxydoroState::LoadedView(ref state) => Subscription::batch(vec![
                 iced::Subscription::from_recipe(StreamWrapper::new(state.rpc_connector.clone()))
-                    .map(Message::StreamUpdate)
-            }
+                    .map(Message::StreamUpdate),
+                iced::Subscription::from_recipe(TimerStreamWrapper::new(
+                    state.rpc_connector.clone(),
+                    state.room.clone(),
+                    self.name.clone(),
+                ))
+                .map(Message::TimerUpdate),
+                iced_native::subscription::events().map(Message::EventOccurred),
+            ]),
             _ => Subscription::none(),
         }
     }
@@ -212,12 +469,33 @@ impl Application for OxydoroUI {
                 .padding(10)
                 .on_submit(Message::SubmitNewTask);
 
+                let theme = self.theme;
+                let selected = &loaded_view_state.selected;
                 let entries = loaded_view_state.tasks.iter().fold(
                     Column::new().padding(20),
-                    |column: Column<Message>, task| column.push(task.view()),
+                    |column: Column<Message>, task| {
+                        column.push(task.view(theme, task.id == *selected))
+                    },
                 );
 
+                let timer = Text::new(
+                    loaded_view_state
+                        .timer
+                        .as_ref()
+                        .map(format_timer)
+                        .unwrap_or_default(),
+                )
+                .size(40);
+
+                let participants = if loaded_view_state.participants.is_empty() {
+                    String::new()
+                } else {
+                    format!("With {}", loaded_view_state.participants.join(", "))
+                };
+
                 let scrollable_entries = Scrollable::new(&mut loaded_view_state.scroll_state)
+                    .push(timer)
+                    .push(Text::new(participants))
                     .push(input)
                     .push(entries)
                     .style(self.theme);
@@ -249,30 +527,107 @@ fn centered_element(content: Element<Message>, theme: Theme) -> Element<Message>
         .into()
 }
 
+fn format_timer(timer: &PomodoroTimer) -> String {
+    let status = match timer.status() {
+        pomodoro_timer::Status::Idle => return String::from("No pomodoro running"),
+        pomodoro_timer::Status::Running => "",
+        pomodoro_timer::Status::Paused => " (paused)",
+        pomodoro_timer::Status::Finished => " (finished)",
+    };
+    let phase = match timer.phase() {
+        pomodoro_timer::Phase::Work => "Focus",
+        pomodoro_timer::Phase::ShortBreak => "Short break",
+        pomodoro_timer::Phase::LongBreak => "Long break",
+    };
+    let remaining_seconds = timer.remaining_ms / 1000;
+    format!(
+        "{}{} {:02}:{:02} - pomodoro {} of {}",
+        phase,
+        status,
+        remaining_seconds / 60,
+        remaining_seconds % 60,
+        timer.cycle_position,
+        timer.cycle_length
+    )
+}
+
 trait ViewModel {
-    fn view(&self) -> Element<Message>;
+    fn view(&self, theme: Theme, selected: bool) -> Element<Message>;
 }
 
 impl ViewModel for Task {
-    fn view(&self) -> Element<Message> {
+    fn view(&self, theme: Theme, selected: bool) -> Element<Message> {
+        let id = self.id.clone().unwrap_or_default();
+        let version = self.version;
+        let mut label = match self.priority() {
+            task::Priority::None | task::Priority::Low => self.title.clone(),
+            task::Priority::Medium => format!("! {}", self.title),
+            task::Priority::High => format!("!! {}", self.title),
+        };
+        if self.estimated_pomodoros > 0 || self.completed_pomodoros > 0 {
+            label += &format!(
+                " ({}/{})",
+                self.completed_pomodoros, self.estimated_pomodoros
+            );
+        }
+        if let Some(due) = self
+            .due
+            .as_ref()
+            .and_then(|due| Local.timestamp_opt(due.seconds, due.nanos as u32).single())
+        {
+            label += &format!(" - due {}", due.format("%b %d"));
+        }
+        for tag in &self.tags {
+            label += &format!(" #{}", tag);
+        }
+        if !self.assignee.is_empty() {
+            label += &format!(" @{}", self.assignee);
+        }
+        if selected {
+            label = format!("> {}", label);
+        }
+        let checkbox = Checkbox::new(self.done, label, move |done| {
+            Message::TaskToggled(id.clone(), version, done)
+        })
+        .style(theme);
         Row::new()
             .width(Length::Fill)
             .align_items(Align::Center)
             .padding(10)
-            .push(Text::new(self.title.clone()).size(20))
+            .push(checkbox)
             .into()
     }
 }
 
 pub fn main() {
-    let light = false;
-    let theme = if light { Theme::Light } else { Theme::Dark };
-    OxydoroUI::run(Settings::with_flags(theme))
+    let args: Args = Args::parse();
+    let connection = match args.connection.connection() {
+        Ok(connection) => connection,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    let theme = if args.light {
+        Theme::Light
+    } else {
+        Theme::Dark
+    };
+    OxydoroUI::run(Settings::with_flags(Flags {
+        theme,
+        connection,
+        room: args.room.unwrap_or_default(),
+        name: args.name.unwrap_or_default(),
+    }))
 }
 
 struct StreamWrapper {
     client: OxydoroClient<Channel>,
     stream: Option<Streaming<SubscribeToTaskUpdatesReply>>,
+    /// Last received revision to resume from after losing the stream
+    revision: u64,
+    /// Epoch of the revision, a restarted server sends a snapshot instead
+    epoch: String,
 }
 
 impl StreamWrapper {
@@ -280,6 +635,8 @@ impl StreamWrapper {
         StreamWrapper {
             client,
             stream: None,
+            revision: 0,
+            epoch: String::new(),
         }
     }
 }
@@ -287,7 +644,7 @@ impl StreamWrapper {
 #[derive(Debug, Clone)]
 enum SubOutput {
     Loading,
-    Message(SubscribeToTaskUpdatesReply),
+    Message(Box<SubscribeToTaskUpdatesReply>),
     Error,
 }
 
@@ -311,19 +668,134 @@ where
             self,
             |mut stream_wrapper| async move {
                 if let Some(ref mut stream) = stream_wrapper.stream {
-                    let message = stream.message().await.unwrap().unwrap();
-                    Some((SubOutput::Message(message), stream_wrapper))
+                    match stream.message().await {
+                        Ok(Some(message)) => {
+                            if let Some(event) = &message.event {
+                                stream_wrapper.revision = event.revision;
+                            }
+                            stream_wrapper.epoch = message.epoch.clone();
+                            Some((SubOutput::Message(Box::new(message)), stream_wrapper))
+                        }
+                        _ => {
+                            stream_wrapper.stream = None;
+                            Some((SubOutput::Error, stream_wrapper))
+                        }
+                    }
                 } else {
                     let tasks_stream = stream_wrapper
                         .client
                         .subscribe_to_task_updates(tonic::Request::new(
-                            SubscribeToTaskUpdatesRequest {},
+                            SubscribeToTaskUpdatesRequest {
+                                since_revision: stream_wrapper.revision,
+                                project_id: None,
+                                epoch: stream_wrapper.epoch.clone(),
+                            },
                         ))
-                        .await
-                        .unwrap()
-                        .into_inner();
-                    stream_wrapper.stream = Some(tasks_stream);
-                    Some((SubOutput::Loading, stream_wrapper))
+                        .await;
+                    match tasks_stream {
+                        Ok(tasks_stream) => {
+                            stream_wrapper.stream = Some(tasks_stream.into_inner());
+                            Some((SubOutput::Loading, stream_wrapper))
+                        }
+                        Err(_) => {
+                            tokio::time::delay_for(RECONNECT_DELAY).await;
+                            Some((SubOutput::Error, stream_wrapper))
+                        }
+                    }
+                }
+            },
+        ))
+    }
+}
+
+/// Keeps the room joined for as long as the stream is open
+struct TimerStreamWrapper {
+    client: OxydoroClient<Channel>,
+    room: String,
+    name: String,
+    stream: Option<Streaming<SubscribeToTimerReply>>,
+}
+
+impl TimerStreamWrapper {
+    fn new(client: OxydoroClient<Channel>, room: String, name: String) -> TimerStreamWrapper {
+        TimerStreamWrapper {
+            client,
+            room,
+            name,
+            stream: None,
+        }
+    }
+
+    /// Room is left together with the last stream, so it is joined again after losing it
+    async fn subscribe(&mut self) -> Result<Streaming<SubscribeToTimerReply>, tonic::Status> {
+        if !self.room.is_empty() {
+            self.client
+                .join_room(tonic::Request::new(JoinRoomRequest {
+                    room: self.room.clone(),
+                    name: self.name.clone(),
+                }))
+                .await?;
+        }
+        let stream = self
+            .client
+            .subscribe_to_timer(tonic::Request::new(SubscribeToTimerRequest {
+                tick_interval_ms: 1000,
+                room: self.room.clone(),
+                name: self.name.clone(),
+            }))
+            .await?;
+        Ok(stream.into_inner())
+    }
+}
+
+#[derive(Debug, Clone)]
+enum TimerSubOutput {
+    Loading,
+    Message(SubscribeToTimerReply),
+    Error,
+}
+
+impl<H, I> iced_native::subscription::Recipe<H, I> for TimerStreamWrapper
+where
+    H: std::hash::Hasher,
+{
+    type Output = TimerSubOutput;
+
+    fn hash(&self, state: &mut H) {
+        use std::hash::Hash;
+
+        std::any::TypeId::of::<Self>().hash(state);
+    }
+
+    fn stream(
+        self: Box<Self>,
+        _input: futures::stream::BoxStream<'static, I>,
+    ) -> futures::stream::BoxStream<'static, Self::Output> {
+        Box::pin(futures::stream::unfold(
+            self,
+            |mut stream_wrapper| async move {
+                if let Some(ref mut stream) = stream_wrapper.stream {
+                    match stream.message().await {
+                        Ok(Some(message)) => {
+                            Some((TimerSubOutput::Message(message), stream_wrapper))
+                        }
+                        // the stream also ends when the room is closed
+                        _ => {
+                            stream_wrapper.stream = None;
+                            Some((TimerSubOutput::Error, stream_wrapper))
+                        }
+                    }
+                } else {
+                    match stream_wrapper.subscribe().await {
+                        Ok(timer_stream) => {
+                            stream_wrapper.stream = Some(timer_stream);
+                            Some((TimerSubOutput::Loading, stream_wrapper))
+                        }
+                        Err(_) => {
+                            tokio::time::delay_for(RECONNECT_DELAY).await;
+                            Some((TimerSubOutput::Error, stream_wrapper))
+                        }
+                    }
                 }
             },
         ))
@@ -0,0 +1,541 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{broadcast, Notify};
+use tonic::Status;
+
+use crate::oxydoro::{
+    pomodoro_session, pomodoro_timer, Interruption, PomodoroSession, PomodoroTimer,
+    SubscribeToTimerReply, TaskId, TimerEvent,
+};
+
+/// Durations of the pomodoro cycle
+#[derive(Debug, Clone, Copy)]
+pub struct PomodoroConfig {
+    pub work_duration: Duration,
+    pub short_break_duration: Duration,
+    pub long_break_duration: Duration,
+    /// Number of pomodoros before a long break
+    pub long_break_interval: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    Work,
+    ShortBreak,
+    LongBreak,
+}
+
+impl Phase {
+    fn to_proto(self) -> pomodoro_timer::Phase {
+        match self {
+            Phase::Work => pomodoro_timer::Phase::Work,
+            Phase::ShortBreak => pomodoro_timer::Phase::ShortBreak,
+            Phase::LongBreak => pomodoro_timer::Phase::LongBreak,
+        }
+    }
+}
+
+enum State {
+    Idle,
+    Running {
+        phase: Phase,
+        task_id: TaskId,
+        deadline: Instant,
+    },
+    Paused {
+        phase: Phase,
+        task_id: TaskId,
+        remaining: Duration,
+    },
+    Finished {
+        phase: Phase,
+        task_id: TaskId,
+    },
+}
+
+/// Events caused by the running interval running out
+pub struct Transition {
+    pub events: Vec<(TimerEvent, PomodoroTimer)>,
+    /// Session of the work interval that just ran out
+    pub completed_session: Option<PomodoroSession>,
+}
+
+/// Authoritative Pomodoro timer shared by all clients
+pub struct Pomodoro {
+    config: PomodoroConfig,
+    state: State,
+    /// Work intervals finished in the current cycle
+    completed_in_cycle: u32,
+    /// Record of the work interval in progress
+    session: Option<PomodoroSession>,
+    /// User who started the cycle in progress, empty for anonymous users
+    owner: String,
+}
+
+impl Pomodoro {
+    pub fn new(config: PomodoroConfig) -> Pomodoro {
+        Pomodoro {
+            config,
+            state: State::Idle,
+            completed_in_cycle: 0,
+            session: None,
+            owner: String::new(),
+        }
+    }
+
+    /// Record of the work interval in progress
+    pub fn session(&self) -> Option<&PomodoroSession> {
+        self.session.as_ref()
+    }
+
+    /// User who started the cycle in progress, empty once it is stopped
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    fn close_session(&mut self, outcome: pomodoro_session::Outcome) -> Option<PomodoroSession> {
+        let mut session = self.session.take()?;
+        session.end = Some(SystemTime::now().into());
+        session.set_outcome(outcome);
+        Some(session)
+    }
+
+    fn phase_duration(&self, phase: Phase) -> Duration {
+        match phase {
+            Phase::Work => self.config.work_duration,
+            Phase::ShortBreak => self.config.short_break_duration,
+            Phase::LongBreak => self.config.long_break_duration,
+        }
+    }
+
+    /// Work in progress can't be replaced but breaks can be skipped
+    fn is_working(&self) -> bool {
+        match self.state {
+            State::Running { phase, .. } | State::Paused { phase, .. } => phase == Phase::Work,
+            State::Idle | State::Finished { .. } => false,
+        }
+    }
+
+    /// Time at which the running interval runs out
+    pub fn deadline(&self) -> Option<Instant> {
+        match self.state {
+            State::Running { deadline, .. } => Some(deadline),
+            _ => None,
+        }
+    }
+
+    pub fn start(&mut self, task_id: TaskId, user: String, now: Instant) -> Result<(), Status> {
+        if self.is_working() {
+            return Err(Status::failed_precondition(
+                "Pomodoro is already in progress",
+            ));
+        }
+        if self.completed_in_cycle >= self.config.long_break_interval {
+            self.completed_in_cycle = 0;
+        }
+        self.session = Some(PomodoroSession {
+            task_id: Some(task_id.clone()),
+            start: Some(SystemTime::now().into()),
+            end: None,
+            planned_duration_ms: self.config.work_duration.as_millis() as u64,
+            outcome: 0,
+            interruptions: vec![],
+            user: user.clone(),
+        });
+        self.owner = user;
+        self.state = State::Running {
+            phase: Phase::Work,
+            task_id,
+            deadline: now + self.config.work_duration,
+        };
+        Ok(())
+    }
+
+    pub fn pause(&mut self, now: Instant) -> Result<(), Status> {
+        match &self.state {
+            State::Running {
+                phase,
+                task_id,
+                deadline,
+            } if *deadline > now => {
+                self.state = State::Paused {
+                    phase: *phase,
+                    task_id: task_id.clone(),
+                    remaining: *deadline - now,
+                };
+                Ok(())
+            }
+            _ => Err(Status::failed_precondition("Pomodoro is not running")),
+        }
+    }
+
+    pub fn resume(&mut self, now: Instant) -> Result<(), Status> {
+        match &self.state {
+            State::Paused {
+                phase,
+                task_id,
+                remaining,
+            } => {
+                self.state = State::Running {
+                    phase: *phase,
+                    task_id: task_id.clone(),
+                    deadline: now + *remaining,
+                };
+                Ok(())
+            }
+            _ => Err(Status::failed_precondition("Pomodoro is not paused")),
+        }
+    }
+
+    /// Add interruption to the work interval in progress
+    pub fn log_interruption(
+        &mut self,
+        interruption: Interruption,
+    ) -> Result<&PomodoroSession, Status> {
+        if !self.is_working() {
+            return Err(Status::failed_precondition("No pomodoro in progress"));
+        }
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| Status::failed_precondition("No pomodoro in progress"))?;
+        session.interruptions.push(interruption);
+        Ok(session)
+    }
+
+    /// Returns aborted session if stopped during work
+    pub fn stop(&mut self) -> Result<Option<PomodoroSession>, Status> {
+        match self.state {
+            State::Idle => Err(Status::failed_precondition("No pomodoro to stop")),
+            _ => {
+                self.state = State::Idle;
+                self.owner.clear();
+                Ok(self.close_session(pomodoro_session::Outcome::Aborted))
+            }
+        }
+    }
+
+    /// Move on to the next phase of the cycle if the running interval ran out
+    ///
+    /// Finished work interval is followed by a short break or
+    /// a long break once every `long_break_interval` pomodoros.
+    pub fn advance(&mut self, now: Instant) -> Transition {
+        let (phase, task_id) = match &self.state {
+            State::Running {
+                phase,
+                task_id,
+                deadline,
+            } if *deadline <= now => (*phase, task_id.clone()),
+            _ => {
+                return Transition {
+                    events: vec![],
+                    completed_session: None,
+                }
+            }
+        };
+        let mut completed_session = None;
+        if phase == Phase::Work {
+            self.completed_in_cycle += 1;
+            completed_session = self.close_session(pomodoro_session::Outcome::Completed);
+        }
+        self.state = State::Finished {
+            phase,
+            task_id: task_id.clone(),
+        };
+        let mut events = vec![(TimerEvent::Finished, self.timer(now))];
+        if phase == Phase::Work {
+            let break_phase = if self.completed_in_cycle >= self.config.long_break_interval {
+                Phase::LongBreak
+            } else {
+                Phase::ShortBreak
+            };
+            self.state = State::Running {
+                phase: break_phase,
+                task_id,
+                deadline: now + self.phase_duration(break_phase),
+            };
+            events.push((TimerEvent::BreakStarted, self.timer(now)));
+        }
+        Transition {
+            events,
+            completed_session,
+        }
+    }
+
+    pub fn timer(&self, now: Instant) -> PomodoroTimer {
+        let (status, phase, task_id, remaining) = match &self.state {
+            State::Idle => (
+                pomodoro_timer::Status::Idle,
+                Phase::Work,
+                None,
+                Duration::from_secs(0),
+            ),
+            State::Running {
+                phase,
+                task_id,
+                deadline,
+            } if *deadline > now => (
+                pomodoro_timer::Status::Running,
+                *phase,
+                Some(task_id.clone()),
+                *deadline - now,
+            ),
+            State::Running { phase, task_id, .. } | State::Finished { phase, task_id } => (
+                pomodoro_timer::Status::Finished,
+                *phase,
+                Some(task_id.clone()),
+                Duration::from_secs(0),
+            ),
+            State::Paused {
+                phase,
+                task_id,
+                remaining,
+            } => (
+                pomodoro_timer::Status::Paused,
+                *phase,
+                Some(task_id.clone()),
+                *remaining,
+            ),
+        };
+        let cycle_position = if self.is_working() {
+            // current pomodoro is not counted as completed yet
+            self.completed_in_cycle + 1
+        } else {
+            self.completed_in_cycle.max(1)
+        };
+        let mut timer = PomodoroTimer {
+            status: 0,
+            task_id,
+            duration_ms: self.phase_duration(phase).as_millis() as u64,
+            remaining_ms: remaining.as_millis() as u64,
+            phase: 0,
+            cycle_position,
+            cycle_length: self.config.long_break_interval,
+        };
+        timer.set_status(status);
+        timer.set_phase(phase.to_proto());
+        timer
+    }
+}
+
+/// Participants are filled in by subscribers as they can change between events
+pub fn timer_reply(event: TimerEvent, timer: PomodoroTimer) -> SubscribeToTimerReply {
+    let mut reply = SubscribeToTimerReply {
+        event: 0,
+        timer: Some(timer),
+        participants: vec![],
+    };
+    reply.set_event(event);
+    reply
+}
+
+/// Member of a room
+pub struct Participant {
+    pub name: String,
+    /// Tells a membership apart from joining again after leaving
+    pub join_id: u64,
+    /// Open timer streams, the participant leaves the room together with the last one
+    pub streams: usize,
+}
+
+/// Pomodoro timer together with everyone following it
+///
+/// The server has one for clients that are not in a room and one for every room
+pub struct SharedTimer {
+    pub pomodoro: Mutex<Pomodoro>,
+    /// Has to be notified every time the timer is modified so that the deadline is re-evaluated
+    pub changed: Arc<Notify>,
+    pub events: broadcast::Sender<SubscribeToTimerReply>,
+    /// Members of a room in the order they joined, empty for the server timer
+    pub participants: Mutex<Vec<Participant>>,
+    next_join_id: AtomicU64,
+    /// Set once the room is gone, the timer might still be referenced for a moment
+    closed: AtomicBool,
+}
+
+impl SharedTimer {
+    pub fn new(config: PomodoroConfig) -> SharedTimer {
+        let (events, _) = broadcast::channel(16);
+        SharedTimer {
+            pomodoro: Mutex::new(Pomodoro::new(config)),
+            changed: Arc::new(Notify::new()),
+            events,
+            participants: Mutex::new(vec![]),
+            next_join_id: AtomicU64::new(1),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Stop advancing the timer
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.changed.notify();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    pub fn current(&self) -> Result<PomodoroTimer, Status> {
+        let pomodoro = self
+            .pomodoro
+            .lock()
+            .map_err(|_| Status::internal("Failed to unlock pomodoro"))?;
+        Ok(pomodoro.timer(Instant::now()))
+    }
+
+    fn lock_participants(&self) -> Result<MutexGuard<'_, Vec<Participant>>, Status> {
+        self.participants
+            .lock()
+            .map_err(|_| Status::internal("Failed to unlock participants"))
+    }
+
+    /// Names of the participants in the order they joined
+    pub fn participants(&self) -> Result<Vec<String>, Status> {
+        let participants = self.lock_participants()?;
+        Ok(participants
+            .iter()
+            .map(|participant| participant.name.clone())
+            .collect())
+    }
+
+    /// Add participant, returns false if it was already in the room
+    pub fn join(&self, name: String) -> Result<bool, Status> {
+        let mut participants = self.lock_participants()?;
+        if participants
+            .iter()
+            .any(|participant| participant.name == name)
+        {
+            return Ok(false);
+        }
+        participants.push(Participant {
+            name,
+            join_id: self.next_join_id.fetch_add(1, Ordering::SeqCst),
+            streams: 0,
+        });
+        Ok(true)
+    }
+
+    /// Count a new timer stream of the participant, returns its join id if it is in the room
+    pub fn connect(&self, name: &str) -> Result<Option<u64>, Status> {
+        let mut participants = self.lock_participants()?;
+        Ok(participants
+            .iter_mut()
+            .find(|participant| participant.name == name)
+            .map(|participant| {
+                participant.streams += 1;
+                participant.join_id
+            }))
+    }
+
+    pub fn send(&self, event: TimerEvent, timer: PomodoroTimer) {
+        // no subscribers is not an error
+        let _ = self.events.send(timer_reply(event, timer));
+    }
+}
+
+/// Advances the pomodoro cycle once the running interval runs out
+///
+/// Stops once the timer is closed or dropped.
+/// `on_session_completed` is called with every work interval that ran out
+pub async fn run_timer<F>(timer: Weak<SharedTimer>, on_session_completed: F)
+where
+    F: Fn(PomodoroSession),
+{
+    loop {
+        // only a weak reference is kept while waiting so that rooms can be dropped
+        let (deadline, changed) = match timer.upgrade() {
+            Some(timer) if !timer.is_closed() => (
+                timer.pomodoro.lock().unwrap().deadline(),
+                Arc::clone(&timer.changed),
+            ),
+            _ => return,
+        };
+        match deadline {
+            Some(deadline) => {
+                tokio::select! {
+                    _ = changed.notified() => continue,
+                    _ = tokio::time::delay_until(deadline.into()) => {}
+                }
+            }
+            None => {
+                changed.notified().await;
+                continue;
+            }
+        }
+        let shared = match timer.upgrade() {
+            Some(shared) if !shared.is_closed() => shared,
+            _ => return,
+        };
+        let transition = {
+            let mut pomodoro = shared.pomodoro.lock().unwrap();
+            let transition = pomodoro.advance(Instant::now());
+            if let Some(session) = &transition.completed_session {
+                // recorded while locked so that it can't race with other session changes
+                on_session_completed(session.clone());
+            }
+            transition
+        };
+        for (event, timer) in transition.events {
+            shared.send(event, timer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> PomodoroConfig {
+        PomodoroConfig {
+            work_duration: Duration::from_secs(25 * 60),
+            short_break_duration: Duration::from_secs(5 * 60),
+            long_break_duration: Duration::from_secs(15 * 60),
+            long_break_interval: 3,
+        }
+    }
+
+    /// Run a whole work interval and return the phase of the break that follows
+    fn work(pomodoro: &mut Pomodoro, now: &mut Instant) -> pomodoro_timer::Phase {
+        let task_id = TaskId {
+            uuid: "a".to_owned(),
+        };
+        pomodoro.start(task_id, String::new(), *now).unwrap();
+        *now += config().work_duration;
+        let transition = pomodoro.advance(*now);
+        assert!(transition.completed_session.is_some());
+        let (event, timer) = transition.events.last().unwrap();
+        assert_eq!(*event, TimerEvent::BreakStarted);
+        let phase = timer.phase();
+        // break runs out before the next pomodoro
+        *now += config().long_break_duration;
+        pomodoro.advance(*now);
+        phase
+    }
+
+    #[test]
+    fn long_break_follows_every_nth_pomodoro() {
+        let mut pomodoro = Pomodoro::new(config());
+        let mut now = Instant::now();
+        let phases: Vec<_> = (0..6).map(|_| work(&mut pomodoro, &mut now)).collect();
+        use pomodoro_timer::Phase::{LongBreak, ShortBreak};
+        assert_eq!(
+            phases,
+            vec![ShortBreak, ShortBreak, LongBreak, ShortBreak, ShortBreak, LongBreak]
+        );
+    }
+
+    #[test]
+    fn advance_does_nothing_before_deadline() {
+        let mut pomodoro = Pomodoro::new(config());
+        let now = Instant::now();
+        let task_id = TaskId {
+            uuid: "a".to_owned(),
+        };
+        pomodoro.start(task_id, String::new(), now).unwrap();
+        let transition = pomodoro.advance(now + Duration::from_secs(60));
+        assert!(transition.events.is_empty());
+        assert!(transition.completed_session.is_none());
+    }
+}
@@ -0,0 +1,160 @@
+use std::cmp::Reverse;
+use tonic::Status;
+
+use crate::auth::Caller;
+use crate::oxydoro::{get_all_tasks_request, GetAllTasksReply, GetAllTasksRequest, Task};
+use crate::store::timestamp_key;
+
+/// Filters, sorts and pages through the task list
+///
+/// Page tokens are offsets into the result, so pages can shift if tasks change in between
+pub struct TaskQuery {
+    done: get_all_tasks_request::DoneFilter,
+    owner: get_all_tasks_request::OwnerFilter,
+    /// Name of the caller the owner filter applies to
+    user: String,
+    tag: String,
+    due_before: Option<(i64, i32)>,
+    query: String,
+    sort: get_all_tasks_request::SortOrder,
+    page_size: Option<usize>,
+    offset: usize,
+}
+
+impl TaskQuery {
+    /// Project is not filtered on here as it has to be checked against the store
+    pub fn new(request: &GetAllTasksRequest, caller: &Caller) -> Result<TaskQuery, Status> {
+        let owner = request.owner();
+        if owner != get_all_tasks_request::OwnerFilter::Visible && caller.is_anonymous() {
+            return Err(Status::invalid_argument(
+                "Owner filter requires authentication",
+            ));
+        }
+        let offset = if request.page_token.is_empty() {
+            0
+        } else {
+            request
+                .page_token
+                .parse()
+                .map_err(|_| Status::invalid_argument("Invalid page token"))?
+        };
+        Ok(TaskQuery {
+            done: request.done(),
+            owner,
+            user: caller.name().to_owned(),
+            tag: request.tag.trim().to_owned(),
+            due_before: request.due_before.as_ref().map(timestamp_key),
+            query: request.query.to_lowercase(),
+            sort: request.sort(),
+            page_size: match request.page_size {
+                0 => None,
+                page_size => Some(page_size as usize),
+            },
+            offset,
+        })
+    }
+
+    fn matches(&self, task: &Task) -> bool {
+        let done = match self.done {
+            get_all_tasks_request::DoneFilter::All => true,
+            get_all_tasks_request::DoneFilter::Open => !task.done,
+            get_all_tasks_request::DoneFilter::Done => task.done,
+        };
+        let due = match (self.due_before, &task.due) {
+            (None, _) => true,
+            (Some(due_before), Some(due)) => timestamp_key(due) < due_before,
+            (Some(_), None) => false,
+        };
+        let owner = match self.owner {
+            get_all_tasks_request::OwnerFilter::Visible => true,
+            get_all_tasks_request::OwnerFilter::CreatedByMe => task.creator == self.user,
+            get_all_tasks_request::OwnerFilter::AssignedToMe => task.assignee == self.user,
+        };
+        done && due
+            && owner
+            && (self.tag.is_empty() || task.tags.contains(&self.tag))
+            && (self.query.is_empty()
+                || task.title.to_lowercase().contains(&self.query)
+                || task.notes.to_lowercase().contains(&self.query))
+    }
+
+    pub fn run<'a, I>(&self, tasks: I) -> GetAllTasksReply
+    where
+        I: Iterator<Item = &'a Task>,
+    {
+        let mut tasks: Vec<&Task> = tasks.filter(|task| self.matches(task)).collect();
+        // sorting is stable so ties keep the manual order
+        match self.sort {
+            get_all_tasks_request::SortOrder::Manual => (),
+            get_all_tasks_request::SortOrder::Due => tasks.sort_by_key(|task| {
+                (
+                    task.due.is_none(),
+                    task.due.as_ref().map(timestamp_key).unwrap_or_default(),
+                )
+            }),
+            get_all_tasks_request::SortOrder::Priority => {
+                tasks.sort_by_key(|task| Reverse(task.priority))
+            }
+            get_all_tasks_request::SortOrder::Title => {
+                tasks.sort_by_cached_key(|task| task.title.to_lowercase())
+            }
+        }
+        let end = match self.page_size {
+            Some(page_size) => self.offset.saturating_add(page_size),
+            None => tasks.len(),
+        };
+        let next_page_token = if end < tasks.len() {
+            end.to_string()
+        } else {
+            String::new()
+        };
+        GetAllTasksReply {
+            tasks: tasks
+                .into_iter()
+                .skip(self.offset)
+                .take(end.saturating_sub(self.offset))
+                .cloned()
+                .collect(),
+            next_page_token,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Request;
+
+    fn tasks(count: usize) -> Vec<Task> {
+        (0..count)
+            .map(|index| Task {
+                title: index.to_string(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn page(tasks: &[Task], page_size: u32, page_token: &str) -> GetAllTasksReply {
+        let request = GetAllTasksRequest {
+            page_size,
+            page_token: page_token.to_owned(),
+            ..Default::default()
+        };
+        let caller = Caller::from_request(&Request::new(()));
+        TaskQuery::new(&request, &caller).unwrap().run(tasks.iter())
+    }
+
+    #[test]
+    fn next_page_token_is_empty_on_last_page() {
+        let tasks = tasks(4);
+        let first = page(&tasks, 2, "");
+        assert_eq!(first.tasks, tasks[..2]);
+        assert_eq!(first.next_page_token, "2");
+        let last = page(&tasks, 2, &first.next_page_token);
+        assert_eq!(last.tasks, tasks[2..]);
+        assert_eq!(last.next_page_token, "");
+        let partial = page(&tasks, 3, "3");
+        assert_eq!(partial.tasks, tasks[3..]);
+        assert_eq!(partial.next_page_token, "");
+    }
+}
@@ -1,48 +1,489 @@
-use std::sync::{Arc, RwLock};
-use tokio::sync::{mpsc, watch};
+// tonic::Status is the error type of every handler
+#![allow(clippy::result_large_err)]
+
+mod auth;
+mod config;
+mod pomodoro;
+mod query;
+mod stats;
+mod storage;
+mod store;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::Poll;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{broadcast, mpsc};
 use tonic::{transport::Server, Request, Response, Status};
 use uuid::Uuid;
 
+use clap::Clap;
+
 use oxydoro::oxydoro_server::{Oxydoro, OxydoroServer};
+use oxydoro::storage::{history_entry, CreateRequest};
 use oxydoro::{
-    CreateTaskReply, CreateTaskRequest, GetAllTasksReply, GetAllTasksRequest,
-    SubscribeToTaskUpdatesReply, SubscribeToTaskUpdatesRequest, Task, TaskId,
+    move_task_request, pomodoro_timer, task, ClearCompletedReply, ClearCompletedRequest,
+    CompleteTaskReply, CompleteTaskRequest, CreateProjectReply, CreateProjectRequest,
+    CreateTaskReply, CreateTaskRequest, DeleteProjectReply, DeleteProjectRequest, DeleteTaskReply,
+    DeleteTaskRequest, GetAllTasksReply, GetAllTasksRequest, GetPomodoroReply, GetPomodoroRequest,
+    GetStatsReply, GetStatsRequest, Interruption, JoinRoomReply, JoinRoomRequest, LeaveRoomReply,
+    LeaveRoomRequest, ListProjectsReply, ListProjectsRequest, ListSessionsReply,
+    ListSessionsRequest, ListUsersReply, ListUsersRequest, LogInterruptionReply,
+    LogInterruptionRequest, MoveTaskReply, MoveTaskRequest, PausePomodoroReply,
+    PausePomodoroRequest, PomodoroSession, PomodoroTimer, Project, ProjectId, RenameProjectReply,
+    RenameProjectRequest, ResumePomodoroReply, ResumePomodoroRequest, StartPomodoroReply,
+    StartPomodoroRequest, StopPomodoroReply, StopPomodoroRequest, SubscribeToTaskUpdatesReply,
+    SubscribeToTaskUpdatesRequest, SubscribeToTimerReply, SubscribeToTimerRequest, Task, TaskId,
+    TimerEvent, UpdateTaskReply, UpdateTaskRequest,
+};
+
+use auth::{Caller, TokenFile};
+use chrono::{Local, TimeZone};
+use config::{default_data_dir, Limits, ServerConfig, TlsFiles};
+use pomodoro::{timer_reply, Participant, Pomodoro, PomodoroConfig, SharedTimer};
+use query::TaskQuery;
+use stats::StatsBuilder;
+use storage::{FileStorage, MemoryStorage, Storage};
+use store::{
+    check_version, descendants, find_project_index, find_task_index, scope_task_event,
+    timestamp_key, Store,
 };
 
 pub mod oxydoro {
     tonic::include_proto!("oxydoro");
+
+    pub mod storage {
+        tonic::include_proto!("oxydoro.storage");
+    }
 }
 
-trait TaskIdWrapper {
+/// Fields that can be listed in update mask of UpdateTask
+const UPDATABLE_TASK_FIELDS: [&str; 9] = [
+    "title",
+    "estimated_pomodoros",
+    "notes",
+    "due",
+    "priority",
+    "tags",
+    "project_id",
+    "parent_id",
+    "assignee",
+];
+
+const DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1:5001";
+const DEFAULT_WORK_MINUTES: u64 = 25;
+const DEFAULT_SHORT_BREAK_MINUTES: u64 = 5;
+const DEFAULT_LONG_BREAK_MINUTES: u64 = 15;
+const DEFAULT_LONG_BREAK_INTERVAL: u32 = 4;
+
+const DEFAULT_TICK_INTERVAL: Duration = Duration::from_secs(1);
+const MIN_TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clap)]
+#[clap(author = "David Weis <dweis7@gmail.com>")]
+struct Args {
+    /// TOML file with defaults for the options below and limits of the server
+    #[clap(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// Address to listen on [default: 127.0.0.1:5001]
+    #[clap(long)]
+    listen: Option<SocketAddr>,
+    /// Directory where tasks are persisted [default: $XDG_DATA_HOME/oxydoro]
+    #[clap(long, parse(from_os_str))]
+    data_dir: Option<PathBuf>,
+    /// Keep tasks in memory only, they are lost when the server stops
+    #[clap(long)]
+    in_memory: bool,
+    /// Length of a single pomodoro in minutes [default: 25]
+    #[clap(long)]
+    work_minutes: Option<u64>,
+    /// Length of a short break in minutes [default: 5]
+    #[clap(long)]
+    short_break_minutes: Option<u64>,
+    /// Length of a long break in minutes [default: 15]
+    #[clap(long)]
+    long_break_minutes: Option<u64>,
+    /// Number of pomodoros before a long break [default: 4]
+    #[clap(long)]
+    long_break_interval: Option<u32>,
+    /// PEM certificate chain of the server, enables TLS together with --tls-key
+    #[clap(long, parse(from_os_str))]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of the server
+    #[clap(long, parse(from_os_str))]
+    tls_key: Option<PathBuf>,
+    /// PEM CA certificate that clients have to present certificates signed by
+    #[clap(long, parse(from_os_str))]
+    tls_client_ca: Option<PathBuf>,
+    /// Reject requests without a valid access token, tokens are kept in the data directory
+    #[clap(long)]
+    require_auth: bool,
+    #[clap(subcommand)]
+    command: Option<AdminCommand>,
+}
+
+/// Administration of the data directory instead of running the server
+///
+/// Token changes apply to a running server right away
+#[derive(Clap)]
+enum AdminCommand {
+    /// Issue a new access token
+    AddToken(TokenNameParam),
+    ListTokens,
+    RevokeToken(TokenNameParam),
+}
+
+#[derive(Clap)]
+struct TokenNameParam {
+    /// Holder of the token
+    name: String,
+}
+
+trait IdWrapper {
     fn new() -> Self;
 }
 
-impl TaskIdWrapper for TaskId {
+fn new_uuid() -> String {
+    Uuid::new_v4()
+        .to_simple()
+        .encode_upper(&mut Uuid::encode_buffer())
+        .to_owned()
+}
+
+impl IdWrapper for TaskId {
     fn new() -> Self {
-        let uuid = Uuid::new_v4();
-        TaskId {
-            uuid: uuid
-                .to_simple()
-                .encode_upper(&mut Uuid::encode_buffer())
-                .to_owned(),
-        }
+        TaskId { uuid: new_uuid() }
+    }
+}
+
+impl IdWrapper for ProjectId {
+    fn new() -> Self {
+        ProjectId { uuid: new_uuid() }
     }
 }
 
+/// Timers of rooms by name
+type Rooms = Mutex<HashMap<String, Arc<SharedTimer>>>;
+
 struct OxydoroStore {
-    tasks: Arc<RwLock<Vec<Task>>>,
-    awaiter: watch::Receiver<()>,
-    waker: watch::Sender<()>,
+    store: Arc<Store>,
+    /// Timer used by requests without a room
+    timer: Arc<SharedTimer>,
+    rooms: Arc<Rooms>,
+    pomodoro_config: PomodoroConfig,
+    /// How long request ids of created tasks are remembered
+    create_request_window: Duration,
+    /// Holders of access tokens if authentication is required
+    tokens: Option<Arc<TokenFile>>,
 }
 
-impl Default for OxydoroStore {
-    fn default() -> Self {
-        let (tx, rx) = watch::channel(());
-        OxydoroStore {
-            tasks: Arc::new(RwLock::new(vec![])),
-            awaiter: rx,
-            waker: tx,
+impl OxydoroStore {
+    fn new(
+        storage: Box<dyn Storage>,
+        pomodoro_config: PomodoroConfig,
+        limits: &Limits,
+        tokens: Option<Arc<TokenFile>>,
+    ) -> std::io::Result<Self> {
+        let store = Store::new(storage, limits)?;
+        Ok(OxydoroStore {
+            store: Arc::new(store),
+            timer: Arc::new(SharedTimer::new(pomodoro_config)),
+            rooms: Arc::new(Mutex::new(HashMap::new())),
+            pomodoro_config,
+            // too long windows are rejected in main
+            create_request_window: limits.create_request_window().unwrap_or(Duration::MAX),
+            tokens,
+        })
+    }
+
+    /// Start background task that advances pomodoros when they run out
+    ///
+    /// Every completed pomodoro is recorded in session history
+    fn spawn_timer(&self, timer: &Arc<SharedTimer>) {
+        let store = Arc::clone(&self.store);
+        tokio::spawn(pomodoro::run_timer(Arc::downgrade(timer), move |session| {
+            record_session(&store, session)
+        }));
+    }
+
+    /// Timer of the room or the server timer if no room is given
+    ///
+    /// Authenticated callers have to join a room before using its timer
+    fn timer(&self, room: &str, caller: &Caller) -> Result<Arc<SharedTimer>, Status> {
+        let room = room.trim();
+        if room.is_empty() {
+            return Ok(Arc::clone(&self.timer));
+        }
+        let rooms = lock_rooms(&self.rooms)?;
+        let timer = rooms
+            .get(room)
+            .ok_or_else(|| Status::not_found(format!("Room {} not found", room)))?;
+        if !caller.is_anonymous()
+            && !timer
+                .participants()?
+                .iter()
+                .any(|name| name == caller.name())
+        {
+            return Err(Status::permission_denied(format!(
+                "Join room {} first",
+                room
+            )));
         }
+        Ok(Arc::clone(timer))
+    }
+
+    /// Apply change to the pomodoro timer, notify subscribers and return its new state
+    fn update_pomodoro<F>(
+        &self,
+        timer: &SharedTimer,
+        event: TimerEvent,
+        change: F,
+    ) -> Result<PomodoroTimer, Status>
+    where
+        F: FnOnce(&mut Pomodoro, Instant) -> Result<(), Status>,
+    {
+        let mut pomodoro = timer
+            .pomodoro
+            .lock()
+            .map_err(|_| Status::internal("Failed to unlock pomodoro"))?;
+        let now = Instant::now();
+        change(&mut pomodoro, now)?;
+        let current = pomodoro.timer(now);
+        timer.changed.notify();
+        timer.send(event, current.clone());
+        Ok(current)
+    }
+
+    /// Only the user who started the server timer can control it, rooms are shared by their participants
+    fn check_timer_owner(
+        &self,
+        timer: &SharedTimer,
+        pomodoro: &Pomodoro,
+        caller: &Caller,
+    ) -> Result<(), Status> {
+        if caller.is_anonymous()
+            || !std::ptr::eq(timer, &*self.timer)
+            || pomodoro.owner().is_empty()
+            || pomodoro.owner() == caller.name()
+        {
+            return Ok(());
+        }
+        Err(Status::permission_denied(
+            "Pomodoro was started by another user",
+        ))
+    }
+
+    /// Tasks can only be assigned to token holders if authentication is required
+    fn check_assignee(&self, assignee: String) -> Result<String, Status> {
+        let assignee = assignee.trim().to_owned();
+        if let (Some(tokens), false) = (&self.tokens, assignee.is_empty()) {
+            let users = tokens
+                .users()
+                .map_err(|_| Status::internal("Failed to read tokens"))?;
+            if !users.contains(&assignee) {
+                return Err(Status::invalid_argument(format!(
+                    "Unknown user {}",
+                    assignee
+                )));
+            }
+        }
+        Ok(assignee)
+    }
+}
+
+/// Leave out the task of a timer that the caller is not allowed to see
+fn hide_task(store: &Store, caller: &Caller, timer: &mut PomodoroTimer) {
+    let task_id = match &timer.task_id {
+        Some(task_id) if !caller.is_anonymous() => task_id,
+        _ => return,
+    };
+    let visible = store
+        .read(|state| {
+            find_task_index(&state.tasks, task_id)
+                .is_ok_and(|index| caller.can_see(&state.tasks[index]))
+        })
+        .unwrap_or(false);
+    if !visible {
+        timer.task_id = None;
+    }
+}
+
+fn check_project_name(name: String) -> Result<String, Status> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(Status::invalid_argument("Project name can't be empty"));
+    }
+    Ok(name.to_owned())
+}
+
+fn check_room_name(room: String) -> Result<String, Status> {
+    let room = room.trim();
+    if room.is_empty() {
+        return Err(Status::invalid_argument("Room name can't be empty"));
+    }
+    Ok(room.to_owned())
+}
+
+/// Authenticated users are always in rooms under their own name
+fn participant_name(caller: &Caller, name: String) -> Result<String, Status> {
+    if !caller.is_anonymous() {
+        return Ok(caller.name().to_owned());
+    }
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(Status::invalid_argument("Missing participant name"));
+    }
+    Ok(name.to_owned())
+}
+
+fn lock_rooms(rooms: &Rooms) -> Result<MutexGuard<'_, HashMap<String, Arc<SharedTimer>>>, Status> {
+    rooms
+        .lock()
+        .map_err(|_| Status::internal("Failed to unlock rooms"))
+}
+
+/// Remove participant from the room if `leaves` returns true for it
+///
+/// Pomodoro of the last participant is aborted together with the room
+fn remove_participant<F>(
+    rooms: &Rooms,
+    store: &Store,
+    room: &str,
+    name: &str,
+    leaves: F,
+) -> Result<(), Status>
+where
+    F: FnOnce(&mut Participant) -> bool,
+{
+    let mut rooms = lock_rooms(rooms)?;
+    let shared = rooms
+        .get(room)
+        .ok_or_else(|| Status::not_found(format!("Room {} not found", room)))?;
+    let is_empty = {
+        let mut participants = shared
+            .participants
+            .lock()
+            .map_err(|_| Status::internal("Failed to unlock participants"))?;
+        let index = participants
+            .iter()
+            .position(|participant| participant.name == name)
+            .ok_or_else(|| {
+                Status::failed_precondition(format!("{} is not in room {}", name, room))
+            })?;
+        if !leaves(&mut participants[index]) {
+            return Ok(());
+        }
+        participants.remove(index);
+        participants.is_empty()
+    };
+    if !is_empty {
+        shared.send(TimerEvent::Left, shared.current()?);
+        return Ok(());
+    }
+    if let Some(shared) = rooms.remove(room) {
+        let mut pomodoro = shared
+            .pomodoro
+            .lock()
+            .map_err(|_| Status::internal("Failed to unlock pomodoro"))?;
+        if let Ok(Some(session)) = pomodoro.stop() {
+            record_session(store, session);
+        }
+        // subscribers might still hold the timer, so it is closed explicitly
+        shared.close();
+    }
+    Ok(())
+}
+
+/// Timer stream of a room participant
+///
+/// Participants leave once their last stream ends so that clients that crash
+/// or never leave don't stay in the room forever
+struct Presence {
+    rooms: Arc<Rooms>,
+    store: Arc<Store>,
+    room: String,
+    name: String,
+    join_id: u64,
+}
+
+impl Drop for Presence {
+    fn drop(&mut self) {
+        let join_id = self.join_id;
+        // fails if the participant left explicitly in the meantime
+        let _ = remove_participant(
+            &self.rooms,
+            &self.store,
+            &self.room,
+            &self.name,
+            |participant| {
+                // joined again after leaving, streams of the earlier membership don't count
+                if participant.join_id != join_id {
+                    return false;
+                }
+                participant.streams -= 1;
+                participant.streams == 0
+            },
+        );
+    }
+}
+
+/// Whether the client of a stream went away, which is otherwise only noticed when sending
+async fn is_disconnected<T>(tx: &mut mpsc::Sender<T>) -> bool {
+    std::future::poll_fn(|cx| {
+        Poll::Ready(match tx.poll_ready(cx) {
+            Poll::Ready(Err(_)) => true,
+            Poll::Ready(Ok(())) => {
+                // nothing is sent, so the reserved slot is given back
+                tx.disarm();
+                false
+            }
+            Poll::Pending => false,
+        })
+    })
+    .await
+}
+
+fn check_priority(priority: i32) -> Result<(), Status> {
+    match task::Priority::from_i32(priority) {
+        Some(_) => Ok(()),
+        None => Err(Status::invalid_argument(format!(
+            "Invalid priority {}",
+            priority
+        ))),
+    }
+}
+
+/// Trim tags and drop empty and repeated ones
+fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(tags.len());
+    for tag in tags {
+        let tag = tag.trim();
+        if !tag.is_empty() && !normalized.iter().any(|existing| existing == tag) {
+            normalized.push(tag.to_owned());
+        }
+    }
+    normalized
+}
+
+/// Deleting tasks of other users together with their parent is not allowed
+fn check_all_visible(caller: &Caller, tasks: &[Task], ids: &[TaskId]) -> Result<(), Status> {
+    let hidden = tasks
+        .iter()
+        .filter(|task| !caller.can_see(task))
+        .any(|task| task.id.as_ref().is_some_and(|id| ids.contains(id)));
+    if hidden {
+        return Err(Status::permission_denied(
+            "Task has subtasks of other users",
+        ));
+    }
+    Ok(())
+}
+
+fn record_session(store: &Store, session: PomodoroSession) {
+    if let Err(error) = store.end_session(session) {
+        println!("Failed to record pomodoro session: {}", error.message());
     }
 }
 
@@ -52,33 +493,100 @@ impl Oxydoro for OxydoroStore {
         &self,
         request: Request<CreateTaskRequest>,
     ) -> Result<Response<CreateTaskReply>, Status> {
+        let caller = Caller::from_request(&request);
         let request_inner = request.into_inner();
-        let new_task = Task {
+        check_priority(request_inner.priority)?;
+        let mut new_task = Task {
             title: request_inner.title,
             id: Some(TaskId::new()),
             done: false,
+            estimated_pomodoros: request_inner.estimated_pomodoros,
+            completed_pomodoros: 0,
+            completed_at: None,
+            version: 1,
+            notes: request_inner.notes,
+            due: request_inner.due,
+            priority: request_inner.priority,
+            tags: normalize_tags(request_inner.tags),
+            project_id: request_inner.project_id,
+            parent_id: request_inner.parent_id,
+            subtask_count: 0,
+            completed_subtask_count: 0,
+            creator: caller.name().to_owned(),
+            assignee: self.check_assignee(request_inner.assignee)?,
         };
-        let mut tasks = self
-            .tasks
-            .write()
-            .map_err(|_| Status::internal("Failed to unlock store"))?;
-        tasks.push(new_task.clone());
-        self.waker.broadcast(()).unwrap();
-        Ok(Response::new(CreateTaskReply {
-            task: Some(new_task),
-        }))
+        let request_id = request_inner.request_id;
+        let now = SystemTime::now();
+        // nothing has expired yet if the window reaches back before the earliest representable time
+        let expired = now
+            .checked_sub(self.create_request_window)
+            .map(|expired| timestamp_key(&expired.into()));
+        let task = self.store.mutate(|state| {
+            state.create_requests.retain(|request| {
+                request
+                    .time
+                    .as_ref()
+                    .is_some_and(|time| expired.is_none_or(|expired| timestamp_key(time) > expired))
+            });
+            if let Some(request) = state.create_requests.iter().find(|request| {
+                // request ids of other users don't have to be unique
+                request.request_id == request_id
+                    && request
+                        .task
+                        .as_ref()
+                        .is_some_and(|task| task.creator == caller.name())
+            }) {
+                // retried request, return the task in its current state unless it was deleted since
+                let original = request.task.clone().unwrap_or_default();
+                let current = original
+                    .id
+                    .as_ref()
+                    .and_then(|id| find_task_index(&state.tasks, id).ok())
+                    .map(|index| state.tasks[index].clone());
+                return Ok(current.unwrap_or(original));
+            }
+            if let Some(parent_id) = &new_task.parent_id {
+                let parent = &state.tasks[find_task_index(&state.tasks, parent_id)?];
+                caller.check_visible(parent)?;
+                if new_task.project_id.is_none() {
+                    new_task.project_id = parent.project_id.clone();
+                }
+            }
+            if let Some(project_id) = &new_task.project_id {
+                find_project_index(&state.projects, project_id)?;
+            }
+            if !request_id.is_empty() {
+                state.create_requests.push(CreateRequest {
+                    request_id,
+                    task: Some(new_task.clone()),
+                    time: Some(now.into()),
+                });
+            }
+            state.tasks.push(new_task.clone());
+            Ok(new_task)
+        })?;
+        Ok(Response::new(CreateTaskReply { task: Some(task) }))
     }
 
     async fn get_all_tasks(
         &self,
-        _: Request<GetAllTasksRequest>,
+        request: Request<GetAllTasksRequest>,
     ) -> Result<Response<GetAllTasksReply>, Status> {
-        let tasks = self
-            .tasks
-            .read()
-            .map_err(|_| Status::internal("Failed to unlock store"))?
-            .clone();
-        let reply = GetAllTasksReply { tasks };
+        let caller = Caller::from_request(&request);
+        let request_inner = request.into_inner();
+        let query = TaskQuery::new(&request_inner, &caller)?;
+        let project_id = request_inner.project_id;
+        let reply = self
+            .store
+            .read(|state| -> Result<GetAllTasksReply, Status> {
+                if let Some(project_id) = &project_id {
+                    find_project_index(&state.projects, project_id)?;
+                }
+                let tasks = state.tasks.iter().filter(|task| {
+                    (project_id.is_none() || task.project_id == project_id) && caller.can_see(task)
+                });
+                Ok(query.run(tasks))
+            })??;
         Ok(Response::new(reply))
     }
 
@@ -90,15 +598,620 @@ impl Oxydoro for OxydoroStore {
     ) -> Result<Response<Self::SubscribeToTaskUpdatesStream>, Status> {
         let (mut tx, rx) = mpsc::channel(16);
 
-        let tasks = Arc::clone(&self.tasks);
-        let mut awaiter = self.awaiter.clone();
+        let since_revision = req.get_ref().since_revision;
+        let project_id = req.get_ref().project_id.clone();
+        if let Some(project_id) = &project_id {
+            self.store
+                .read(|state| find_project_index(&state.projects, project_id))??;
+        }
+        let caller = Caller::from_request(&req);
+        let store = Arc::clone(&self.store);
+        let (mut events, mut receiver) = self
+            .store
+            .subscribe_to_tasks(since_revision, &req.get_ref().epoch)?;
+        let epoch = self.store.epoch().to_owned();
+        tokio::spawn(async move {
+            let mut revision = since_revision;
+            loop {
+                for event in events {
+                    revision = event.event.revision;
+                    let event = match &project_id {
+                        Some(project_id) => match scope_task_event(event, project_id) {
+                            Some(event) => event,
+                            None => continue,
+                        },
+                        None => event,
+                    };
+                    let event = match store.scope_task_event(event, &caller) {
+                        Some(event) => event,
+                        None => continue,
+                    };
+                    let res = tx
+                        .send(Ok(SubscribeToTaskUpdatesReply {
+                            // routing information stays on the server
+                            event: Some(event.event),
+                            epoch: epoch.clone(),
+                        }))
+                        .await;
+                    if res.is_err() {
+                        println!("Client disconnected {:?}", req.remote_addr());
+                        return;
+                    }
+                }
+                events = match receiver.recv().await {
+                    Ok(event) => vec![event],
+                    Err(broadcast::RecvError::Lagged(_)) => {
+                        // slow clients are dropped instead of holding back everyone else
+                        let status = Status::aborted(format!(
+                            "Subscriber fell behind, resubscribe from revision {}",
+                            revision
+                        ));
+                        let _ = tx.send(Err(status)).await;
+                        return;
+                    }
+                    Err(broadcast::RecvError::Closed) => return,
+                };
+            }
+        });
+
+        Ok(Response::new(rx))
+    }
+
+    async fn update_task(
+        &self,
+        request: Request<UpdateTaskRequest>,
+    ) -> Result<Response<UpdateTaskReply>, Status> {
+        let caller = Caller::from_request(&request);
+        let request_inner = request.into_inner();
+        let update_mask = match request_inner.update_mask {
+            Some(update_mask) => update_mask.paths,
+            None => vec![String::from("title")],
+        };
+        if let Some(path) = update_mask
+            .iter()
+            .find(|path| !UPDATABLE_TASK_FIELDS.contains(&path.as_str()))
+        {
+            return Err(Status::invalid_argument(format!(
+                "Field {} can't be updated",
+                path
+            )));
+        }
+        check_priority(request_inner.priority)?;
+        let title = request_inner.title;
+        let estimated_pomodoros = request_inner.estimated_pomodoros;
+        let notes = request_inner.notes;
+        let due = request_inner.due;
+        let priority = request_inner.priority;
+        let tags = normalize_tags(request_inner.tags);
+        let project_id = request_inner.project_id;
+        let parent_id = request_inner.parent_id;
+        let assignee = if update_mask.iter().any(|path| path == "assignee") {
+            self.check_assignee(request_inner.assignee)?
+        } else {
+            String::new()
+        };
+        let expected_version = request_inner.expected_version;
+        let task = self
+            .store
+            .modify_task(&caller, request_inner.id, expected_version, |task| {
+                for path in &update_mask {
+                    match path.as_str() {
+                        "title" => task.title = title.clone(),
+                        "estimated_pomodoros" => task.estimated_pomodoros = estimated_pomodoros,
+                        "notes" => task.notes = notes.clone(),
+                        "due" => task.due = due.clone(),
+                        "priority" => task.priority = priority,
+                        "tags" => task.tags = tags.clone(),
+                        "project_id" => task.project_id = project_id.clone(),
+                        "parent_id" => task.parent_id = parent_id.clone(),
+                        "assignee" => task.assignee = assignee.clone(),
+                        _ => unreachable!(),
+                    }
+                }
+            })?;
+        Ok(Response::new(UpdateTaskReply { task: Some(task) }))
+    }
+
+    async fn complete_task(
+        &self,
+        request: Request<CompleteTaskRequest>,
+    ) -> Result<Response<CompleteTaskReply>, Status> {
+        let caller = Caller::from_request(&request);
+        let request_inner = request.into_inner();
+        let done = request_inner.done;
+        let expected_version = request_inner.expected_version;
+        let mut changed = false;
+        let task = self
+            .store
+            .modify_task(&caller, request_inner.id, expected_version, |task| {
+                if done && !task.done {
+                    task.completed_at = Some(SystemTime::now().into());
+                } else if !done {
+                    task.completed_at = None;
+                }
+                changed = task.done != done;
+                task.done = done;
+            })?;
+        // stats count completions from history so that they survive deleting the task
+        if changed {
+            if let Err(error) = self.store.record_completion(&task, caller.name()) {
+                println!("Failed to record task completion: {}", error.message());
+            }
+        }
+        Ok(Response::new(CompleteTaskReply { task: Some(task) }))
+    }
+
+    async fn delete_task(
+        &self,
+        request: Request<DeleteTaskRequest>,
+    ) -> Result<Response<DeleteTaskReply>, Status> {
+        let caller = Caller::from_request(&request);
+        let request_inner = request.into_inner();
+        let expected_version = request_inner.expected_version;
+        let id = request_inner
+            .id
+            .ok_or_else(|| Status::invalid_argument("Missing task id"))?;
+        let (task, deleted_subtask_count) = self.store.mutate(|state| {
+            let index = find_task_index(&state.tasks, &id)?;
+            caller.check_visible(&state.tasks[index])?;
+            check_version(&state.tasks[index], expected_version)?;
+            let subtasks = descendants(&state.tasks, &id);
+            check_all_visible(&caller, &state.tasks, &subtasks)?;
+            let task = state.tasks.remove(index);
+            state
+                .tasks
+                .retain(|task| !subtasks.iter().any(|id| task.id.as_ref() == Some(id)));
+            Ok((task, subtasks.len() as u32))
+        })?;
+        Ok(Response::new(DeleteTaskReply {
+            task: Some(task),
+            deleted_subtask_count,
+        }))
+    }
+
+    async fn move_task(
+        &self,
+        request: Request<MoveTaskRequest>,
+    ) -> Result<Response<MoveTaskReply>, Status> {
+        let caller = Caller::from_request(&request);
+        let request_inner = request.into_inner();
+        let id = request_inner
+            .id
+            .ok_or_else(|| Status::invalid_argument("Missing task id"))?;
+        let (target, after) = match request_inner.position {
+            Some(move_task_request::Position::Before(target)) => (target, false),
+            Some(move_task_request::Position::After(target)) => (target, true),
+            None => return Err(Status::invalid_argument("Missing position")),
+        };
+        if target == id {
+            return Err(Status::invalid_argument(
+                "Task can't be placed next to itself",
+            ));
+        }
+        let task = self.store.mutate(|state| {
+            let index = find_task_index(&state.tasks, &id)?;
+            caller.check_visible(&state.tasks[index])?;
+            let task = state.tasks.remove(index);
+            let mut index = find_task_index(&state.tasks, &target)?;
+            caller.check_visible(&state.tasks[index])?;
+            if state.tasks[index].project_id != task.project_id {
+                return Err(Status::invalid_argument(
+                    "Task can only be placed next to tasks of the same project",
+                ));
+            }
+            if after {
+                index += 1;
+            }
+            state.tasks.insert(index, task.clone());
+            Ok(task)
+        })?;
+        Ok(Response::new(MoveTaskReply { task: Some(task) }))
+    }
+
+    async fn clear_completed(
+        &self,
+        request: Request<ClearCompletedRequest>,
+    ) -> Result<Response<ClearCompletedReply>, Status> {
+        let caller = Caller::from_request(&request);
+        let removed_count = self.store.mutate(|state| {
+            let original_count = state.tasks.len();
+            let mut removed = vec![];
+            for id in state
+                .tasks
+                .iter()
+                .filter(|task| task.done && caller.can_see(task))
+                .filter_map(|task| task.id.as_ref())
+            {
+                // tasks with subtasks of other users are kept together with them
+                let subtasks = descendants(&state.tasks, id);
+                if check_all_visible(&caller, &state.tasks, &subtasks).is_ok() {
+                    removed.push(id.clone());
+                    removed.extend(subtasks);
+                }
+            }
+            state
+                .tasks
+                .retain(|task| !removed.iter().any(|id| task.id.as_ref() == Some(id)));
+            Ok((original_count - state.tasks.len()) as u32)
+        })?;
+        Ok(Response::new(ClearCompletedReply { removed_count }))
+    }
+
+    async fn start_pomodoro(
+        &self,
+        request: Request<StartPomodoroRequest>,
+    ) -> Result<Response<StartPomodoroReply>, Status> {
+        let caller = Caller::from_request(&request);
+        let request_inner = request.into_inner();
+        let task_id = request_inner
+            .task_id
+            .ok_or_else(|| Status::invalid_argument("Missing task id"))?;
+        self.store.read(|state| {
+            caller.check_visible(&state.tasks[find_task_index(&state.tasks, &task_id)?])
+        })??;
+        let shared = self.timer(&request_inner.room, &caller)?;
+        // rooms only live in memory, so only sessions of the server timer are recovered on restart
+        let persist = Arc::ptr_eq(&shared, &self.timer);
+        // session records are written while the timer is locked to keep them in order
+        let timer = self.update_pomodoro(&shared, TimerEvent::Started, |pomodoro, now| {
+            pomodoro.start(task_id, caller.name().to_owned(), now)?;
+            if let Some(session) = pomodoro.session().filter(|_| persist) {
+                if let Err(error) = self.store.save_active_session(session.clone()) {
+                    println!("Failed to record pomodoro session: {}", error.message());
+                }
+            }
+            Ok(())
+        })?;
+        Ok(Response::new(StartPomodoroReply { timer: Some(timer) }))
+    }
+
+    async fn pause_pomodoro(
+        &self,
+        request: Request<PausePomodoroRequest>,
+    ) -> Result<Response<PausePomodoroReply>, Status> {
+        let caller = Caller::from_request(&request);
+        let shared = self.timer(&request.get_ref().room, &caller)?;
+        let timer = self.update_pomodoro(&shared, TimerEvent::Paused, |pomodoro, now| {
+            self.check_timer_owner(&shared, pomodoro, &caller)?;
+            pomodoro.pause(now)
+        })?;
+        Ok(Response::new(PausePomodoroReply { timer: Some(timer) }))
+    }
+
+    async fn resume_pomodoro(
+        &self,
+        request: Request<ResumePomodoroRequest>,
+    ) -> Result<Response<ResumePomodoroReply>, Status> {
+        let caller = Caller::from_request(&request);
+        let shared = self.timer(&request.get_ref().room, &caller)?;
+        let timer = self.update_pomodoro(&shared, TimerEvent::Resumed, |pomodoro, now| {
+            self.check_timer_owner(&shared, pomodoro, &caller)?;
+            pomodoro.resume(now)
+        })?;
+        Ok(Response::new(ResumePomodoroReply { timer: Some(timer) }))
+    }
+
+    async fn stop_pomodoro(
+        &self,
+        request: Request<StopPomodoroRequest>,
+    ) -> Result<Response<StopPomodoroReply>, Status> {
+        let caller = Caller::from_request(&request);
+        let shared = self.timer(&request.get_ref().room, &caller)?;
+        let timer = self.update_pomodoro(&shared, TimerEvent::Stopped, |pomodoro, _| {
+            self.check_timer_owner(&shared, pomodoro, &caller)?;
+            if let Some(session) = pomodoro.stop()? {
+                record_session(&self.store, session);
+            }
+            Ok(())
+        })?;
+        Ok(Response::new(StopPomodoroReply { timer: Some(timer) }))
+    }
+
+    async fn get_pomodoro(
+        &self,
+        request: Request<GetPomodoroRequest>,
+    ) -> Result<Response<GetPomodoroReply>, Status> {
+        let caller = Caller::from_request(&request);
+        let shared = self.timer(&request.get_ref().room, &caller)?;
+        let mut timer = shared.current()?;
+        hide_task(&self.store, &caller, &mut timer);
+        Ok(Response::new(GetPomodoroReply { timer: Some(timer) }))
+    }
+
+    async fn list_sessions(
+        &self,
+        request: Request<ListSessionsRequest>,
+    ) -> Result<Response<ListSessionsReply>, Status> {
+        let caller = Caller::from_request(&request);
+        let request_inner = request.into_inner();
+        let since = request_inner.since.as_ref().map(timestamp_key);
+        let until = request_inner.until.as_ref().map(timestamp_key);
+        let task_id = request_inner.task_id;
+        let sessions = self.store.read_history(|history| {
+            history
+                .iter()
+                .filter(|entry| caller.can_see_entry(entry))
+                .filter_map(|entry| match &entry.kind {
+                    Some(history_entry::Kind::Session(session)) => Some(session),
+                    _ => None,
+                })
+                .filter(|session| {
+                    let start = session
+                        .start
+                        .as_ref()
+                        .map(timestamp_key)
+                        .unwrap_or_default();
+                    since.is_none_or(|since| start >= since)
+                        && until.is_none_or(|until| start < until)
+                        && (task_id.is_none() || session.task_id == task_id)
+                })
+                .cloned()
+                .collect()
+        })?;
+        Ok(Response::new(ListSessionsReply { sessions }))
+    }
+
+    async fn get_stats(
+        &self,
+        request: Request<GetStatsRequest>,
+    ) -> Result<Response<GetStatsReply>, Status> {
+        let caller = Caller::from_request(&request);
+        let request_inner = request.into_inner();
+        let stats = StatsBuilder::new(
+            request_inner.period(),
+            request_inner.utc_offset_minutes,
+            request_inner.since.as_ref(),
+            request_inner.until.as_ref(),
+        )?;
+        let now = SystemTime::now().into();
+        let reply = self.store.read_history(|history| {
+            let history = history.iter().filter(|entry| caller.can_see_entry(entry));
+            stats.build(history, &now)
+        })?;
+        Ok(Response::new(reply))
+    }
+
+    async fn create_project(
+        &self,
+        request: Request<CreateProjectRequest>,
+    ) -> Result<Response<CreateProjectReply>, Status> {
+        let name = check_project_name(request.into_inner().name)?;
+        let project = Project {
+            id: Some(ProjectId::new()),
+            name,
+        };
+        self.store.mutate(|state| {
+            state.projects.push(project.clone());
+            Ok(())
+        })?;
+        Ok(Response::new(CreateProjectReply {
+            project: Some(project),
+        }))
+    }
+
+    async fn list_projects(
+        &self,
+        _: Request<ListProjectsRequest>,
+    ) -> Result<Response<ListProjectsReply>, Status> {
+        let projects = self.store.read(|state| state.projects.clone())?;
+        Ok(Response::new(ListProjectsReply { projects }))
+    }
+
+    async fn rename_project(
+        &self,
+        request: Request<RenameProjectRequest>,
+    ) -> Result<Response<RenameProjectReply>, Status> {
+        let request_inner = request.into_inner();
+        let id = request_inner
+            .id
+            .ok_or_else(|| Status::invalid_argument("Missing project id"))?;
+        let name = check_project_name(request_inner.name)?;
+        let project = self.store.mutate(|state| {
+            let index = find_project_index(&state.projects, &id)?;
+            state.projects[index].name = name;
+            Ok(state.projects[index].clone())
+        })?;
+        Ok(Response::new(RenameProjectReply {
+            project: Some(project),
+        }))
+    }
+
+    async fn delete_project(
+        &self,
+        request: Request<DeleteProjectRequest>,
+    ) -> Result<Response<DeleteProjectReply>, Status> {
+        let caller = Caller::from_request(&request);
+        let request_inner = request.into_inner();
+        let id = request_inner
+            .id
+            .ok_or_else(|| Status::invalid_argument("Missing project id"))?;
+        let delete_tasks = request_inner.delete_tasks;
+        let (project, deleted_task_count) = self.store.mutate(|state| {
+            let index = find_project_index(&state.projects, &id)?;
+            if state
+                .tasks
+                .iter()
+                .any(|task| task.project_id.as_ref() == Some(&id) && !caller.can_see(task))
+            {
+                return Err(Status::permission_denied(
+                    "Project has tasks of other users",
+                ));
+            }
+            let original_count = state.tasks.len();
+            state
+                .tasks
+                .retain(|task| task.project_id.as_ref() != Some(&id));
+            let deleted_task_count = (original_count - state.tasks.len()) as u32;
+            if deleted_task_count > 0 && !delete_tasks {
+                return Err(Status::failed_precondition(format!(
+                    "Project still has {} tasks",
+                    deleted_task_count
+                )));
+            }
+            Ok((state.projects.remove(index), deleted_task_count))
+        })?;
+        Ok(Response::new(DeleteProjectReply {
+            project: Some(project),
+            deleted_task_count,
+        }))
+    }
+
+    async fn list_users(
+        &self,
+        _: Request<ListUsersRequest>,
+    ) -> Result<Response<ListUsersReply>, Status> {
+        let users = match &self.tokens {
+            Some(tokens) => tokens
+                .users()
+                .map_err(|_| Status::internal("Failed to read tokens"))?,
+            None => vec![],
+        };
+        Ok(Response::new(ListUsersReply { users }))
+    }
+
+    async fn join_room(
+        &self,
+        request: Request<JoinRoomRequest>,
+    ) -> Result<Response<JoinRoomReply>, Status> {
+        let caller = Caller::from_request(&request);
+        let request_inner = request.into_inner();
+        let room = check_room_name(request_inner.room)?;
+        let name = participant_name(&caller, request_inner.name)?;
+        let mut rooms = lock_rooms(&self.rooms)?;
+        let shared = match rooms.get(&room) {
+            Some(shared) => Arc::clone(shared),
+            None => {
+                let shared = Arc::new(SharedTimer::new(self.pomodoro_config));
+                self.spawn_timer(&shared);
+                rooms.insert(room, Arc::clone(&shared));
+                shared
+            }
+        };
+        // joining again is not an error
+        if shared.join(name)? {
+            shared.send(TimerEvent::Joined, shared.current()?);
+        }
+        Ok(Response::new(JoinRoomReply {
+            timer: Some(shared.current()?),
+            participants: shared.participants()?,
+        }))
+    }
+
+    async fn leave_room(
+        &self,
+        request: Request<LeaveRoomRequest>,
+    ) -> Result<Response<LeaveRoomReply>, Status> {
+        let caller = Caller::from_request(&request);
+        let request_inner = request.into_inner();
+        let room = check_room_name(request_inner.room)?;
+        let name = participant_name(&caller, request_inner.name)?;
+        remove_participant(&self.rooms, &self.store, &room, &name, |_| true)?;
+        Ok(Response::new(LeaveRoomReply {}))
+    }
+
+    async fn log_interruption(
+        &self,
+        request: Request<LogInterruptionRequest>,
+    ) -> Result<Response<LogInterruptionReply>, Status> {
+        let caller = Caller::from_request(&request);
+        let request_inner = request.into_inner();
+        let shared = self.timer(&request_inner.room, &caller)?;
+        let interruption = Interruption {
+            kind: request_inner.kind,
+            note: request_inner.note,
+            time: Some(SystemTime::now().into()),
+        };
+        let mut pomodoro = shared
+            .pomodoro
+            .lock()
+            .map_err(|_| Status::internal("Failed to unlock pomodoro"))?;
+        self.check_timer_owner(&shared, &pomodoro, &caller)?;
+        let session = pomodoro.log_interruption(interruption)?.clone();
+        if Arc::ptr_eq(&shared, &self.timer) {
+            self.store.save_active_session(session.clone())?;
+        }
+        Ok(Response::new(LogInterruptionReply {
+            session: Some(session),
+        }))
+    }
+
+    type SubscribeToTimerStream = mpsc::Receiver<Result<SubscribeToTimerReply, Status>>;
+
+    async fn subscribe_to_timer(
+        &self,
+        req: Request<SubscribeToTimerRequest>,
+    ) -> Result<Response<Self::SubscribeToTimerStream>, Status> {
+        let tick_interval = match req.get_ref().tick_interval_ms {
+            0 => DEFAULT_TICK_INTERVAL,
+            tick_interval_ms => {
+                Duration::from_millis(tick_interval_ms as u64).max(MIN_TICK_INTERVAL)
+            }
+        };
+        let (mut tx, rx) = mpsc::channel(16);
+
+        let caller = Caller::from_request(&req);
+        let room = req.get_ref().room.trim().to_owned();
+        let shared = self.timer(&room, &caller)?;
+        // anonymous subscribers without a name only watch the timer
+        let name = participant_name(&caller, req.get_ref().name.clone()).ok();
+        let presence = match name.filter(|_| !room.is_empty()) {
+            Some(name) => shared.connect(&name)?.map(|join_id| Presence {
+                rooms: Arc::clone(&self.rooms),
+                store: Arc::clone(&self.store),
+                room,
+                name,
+                join_id,
+            }),
+            None => None,
+        };
+        let mut initial = timer_reply(TimerEvent::Tick, shared.current()?);
+        initial.participants = shared.participants()?;
+        let mut events = shared.events.subscribe();
+        let store = Arc::clone(&self.store);
+        // stream ends once the room is closed
+        let timer = Arc::downgrade(&shared);
         tokio::spawn(async move {
+            // participant leaves once the task ends
+            let _presence = presence;
+            if let Some(timer) = &mut initial.timer {
+                hide_task(&store, &caller, timer);
+            }
+            if tx.send(Ok(initial)).await.is_err() {
+                return;
+            }
+            let mut ticks = tokio::time::interval(tick_interval);
             loop {
-                awaiter.recv().await;
-                let tasks = tasks.read().unwrap().clone();
-                let res = tx.send(Ok(SubscribeToTaskUpdatesReply { tasks })).await;
-                if res.is_err() {
-                    println!("Client disconnected {:?}", req.remote_addr());
+                let mut reply = tokio::select! {
+                    event = events.recv() => match event {
+                        Ok(reply) => reply,
+                        // next tick brings subscriber up to date
+                        Err(broadcast::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::RecvError::Closed) => return,
+                    },
+                    _ = ticks.tick() => {
+                        let current = match timer.upgrade().map(|shared| shared.current()) {
+                            Some(Ok(current)) => current,
+                            _ => return,
+                        };
+                        if current.status() != pomodoro_timer::Status::Running {
+                            // nothing is sent while idle, so clients going away are noticed here
+                            if is_disconnected(&mut tx).await {
+                                return;
+                            }
+                            continue;
+                        }
+                        timer_reply(TimerEvent::Tick, current)
+                    }
+                };
+                reply.participants = match timer.upgrade().map(|shared| shared.participants()) {
+                    Some(Ok(participants)) => participants,
+                    _ => return,
+                };
+                if let Some(timer) = &mut reply.timer {
+                    hide_task(&store, &caller, timer);
+                }
+                if tx.send(Ok(reply)).await.is_err() {
+                    println!("Timer client disconnected {:?}", req.remote_addr());
                     return;
                 }
             }
@@ -110,15 +1223,266 @@ impl Oxydoro for OxydoroStore {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let address = "127.0.0.1:5001".parse()?;
-    let oxydoro_service = OxydoroStore::default();
+    let args: Args = Args::parse();
+    let config = match &args.config {
+        Some(path) => ServerConfig::load(path)?,
+        None => ServerConfig::default(),
+    };
+    let address = match args.listen.or(config.listen) {
+        Some(address) => address,
+        None => DEFAULT_LISTEN_ADDRESS.parse()?,
+    };
+    let in_memory = args.in_memory || config.in_memory;
+    let data_dir = match (args.data_dir.or(config.data_dir), in_memory) {
+        (Some(_), true) => return Err("A data directory can't be used in memory only".into()),
+        (Some(data_dir), false) => Some(data_dir),
+        (None, true) => None,
+        (None, false) => Some(default_data_dir().ok_or("No home directory, set --data-dir")?),
+    };
+    if let Some(command) = args.command {
+        let data_dir = data_dir.ok_or("Tokens are kept in the data directory, set --data-dir")?;
+        return run_admin_command(command, &TokenFile::new(&data_dir));
+    }
+    let require_auth = args.require_auth || config.require_auth;
+    let token_file = match (&data_dir, require_auth) {
+        (Some(data_dir), true) => Some(Arc::new(TokenFile::new(data_dir))),
+        (None, true) => return Err("Authentication requires a data directory for tokens".into()),
+        (_, false) => None,
+    };
+    let storage: Box<dyn Storage> = match data_dir {
+        Some(data_dir) => Box::new(FileStorage::new(data_dir)?),
+        None => Box::new(MemoryStorage),
+    };
+    let work_minutes = args
+        .work_minutes
+        .or(config.work_minutes)
+        .unwrap_or(DEFAULT_WORK_MINUTES);
+    let short_break_minutes = args
+        .short_break_minutes
+        .or(config.short_break_minutes)
+        .unwrap_or(DEFAULT_SHORT_BREAK_MINUTES);
+    let long_break_minutes = args
+        .long_break_minutes
+        .or(config.long_break_minutes)
+        .unwrap_or(DEFAULT_LONG_BREAK_MINUTES);
+    let long_break_interval = args
+        .long_break_interval
+        .or(config.long_break_interval)
+        .unwrap_or(DEFAULT_LONG_BREAK_INTERVAL);
+    if long_break_interval == 0 {
+        return Err("Long break interval has to be at least 1".into());
+    }
+    if config.limits.change_log_capacity == 0 || config.limits.subscriber_buffer == 0 {
+        return Err("Change log capacity and subscriber buffer have to be at least 1".into());
+    }
+    if config
+        .limits
+        .create_request_window()
+        .and_then(|window| SystemTime::now().checked_sub(window))
+        .is_none()
+    {
+        return Err("Create request window is too long".into());
+    }
+    let pomodoro_config = PomodoroConfig {
+        work_duration: Duration::from_secs(work_minutes * 60),
+        short_break_duration: Duration::from_secs(short_break_minutes * 60),
+        long_break_duration: Duration::from_secs(long_break_minutes * 60),
+        long_break_interval,
+    };
+    let tls_files = TlsFiles {
+        cert: args.tls_cert.or(config.tls.cert),
+        key: args.tls_key.or(config.tls.key),
+        client_ca: args.tls_client_ca.or(config.tls.client_ca),
+    };
+    let mut server = Server::builder();
+    if let Some(tls_config) = tls_files.load()? {
+        server = server.tls_config(tls_config)?;
+    }
+    if let Some(token_file) = &token_file {
+        if token_file.load()?.is_empty() {
+            println!("No access tokens yet, issue one with add-token");
+        }
+    }
+    let oxydoro_service =
+        OxydoroStore::new(storage, pomodoro_config, &config.limits, token_file.clone())?;
+    oxydoro_service.spawn_timer(&oxydoro_service.timer);
+
+    // interceptor is also needed without authentication to drop users set by clients
+    let service = OxydoroServer::with_interceptor(oxydoro_service, auth::interceptor(token_file));
 
     println!("Oxydoro service at {}", address);
 
-    Server::builder()
-        .add_service(OxydoroServer::new(oxydoro_service))
-        .serve(address)
-        .await?;
+    server.add_service(service).serve(address).await?;
+
+    Ok(())
+}
 
+fn run_admin_command(
+    command: AdminCommand,
+    token_file: &TokenFile,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        AdminCommand::AddToken(params) => {
+            let token = token_file
+                .add(&params.name)
+                .map_err(|error| error.to_string())?;
+            println!("Token for {}: {}", params.name.trim(), token);
+            println!("It is not stored and can't be shown again");
+        }
+        AdminCommand::ListTokens => {
+            for token in token_file.load()? {
+                let created = token
+                    .created
+                    .and_then(|created| {
+                        Local
+                            .timestamp_opt(created.seconds, created.nanos as u32)
+                            .single()
+                    })
+                    .map(|created| created.format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_default();
+                println!("{} created {}", token.name, created);
+            }
+        }
+        AdminCommand::RevokeToken(params) => {
+            if !token_file.revoke(&params.name)? {
+                return Err(format!("No token named {}", params.name).into());
+            }
+            println!("Revoked token {}", params.name);
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use auth::request_as;
+
+    fn service() -> OxydoroStore {
+        let pomodoro_config = PomodoroConfig {
+            work_duration: Duration::from_secs(25 * 60),
+            short_break_duration: Duration::from_secs(5 * 60),
+            long_break_duration: Duration::from_secs(15 * 60),
+            long_break_interval: 4,
+        };
+        OxydoroStore::new(
+            Box::new(MemoryStorage),
+            pomodoro_config,
+            &Limits::default(),
+            None,
+        )
+        .unwrap()
+    }
+
+    async fn create_task(
+        service: &OxydoroStore,
+        user: Option<&str>,
+        title: &str,
+        request_id: &str,
+    ) -> Task {
+        let request = CreateTaskRequest {
+            title: title.to_owned(),
+            request_id: request_id.to_owned(),
+            ..Default::default()
+        };
+        let request = match user {
+            Some(user) => request_as(user, request),
+            None => Request::new(request),
+        };
+        service
+            .create_task(request)
+            .await
+            .unwrap()
+            .into_inner()
+            .task
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn create_task_retry_returns_the_original_task() {
+        let service = service();
+        let task = create_task(&service, None, "a", "request").await;
+        assert_eq!(create_task(&service, None, "a", "request").await, task);
+        let other = create_task(&service, None, "a", "other request").await;
+        assert_ne!(other.id, task.id);
+        let count = service.store.read(|state| state.tasks.len()).unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[tokio::test]
+    async fn create_task_accepts_windows_reaching_before_any_timestamp() {
+        let limits = Limits {
+            create_request_window_hours: u64::MAX,
+            ..Limits::default()
+        };
+        let pomodoro_config = service().pomodoro_config;
+        let service =
+            OxydoroStore::new(Box::new(MemoryStorage), pomodoro_config, &limits, None).unwrap();
+        let task = create_task(&service, None, "a", "request").await;
+        assert_eq!(create_task(&service, None, "a", "request").await, task);
+    }
+
+    #[tokio::test]
+    async fn create_task_retry_is_scoped_to_the_creator() {
+        let service = service();
+        let task = create_task(&service, Some("ann"), "a", "request").await;
+        let other = create_task(&service, Some("bob"), "b", "request").await;
+        assert_ne!(other.id, task.id);
+        assert_eq!(other.creator, "bob");
+        assert_eq!(
+            create_task(&service, Some("ann"), "a", "request").await,
+            task
+        );
+    }
+
+    #[tokio::test]
+    async fn update_task_hides_parents_of_other_users() {
+        let service = service();
+        let private = create_task(&service, Some("ann"), "a", "").await;
+        let task = create_task(&service, Some("bob"), "b", "").await;
+        let request = UpdateTaskRequest {
+            id: task.id,
+            parent_id: private.id,
+            update_mask: Some(prost_types::FieldMask {
+                paths: vec!["parent_id".to_owned()],
+            }),
+            ..Default::default()
+        };
+        let error = service
+            .update_task(request_as("bob", request))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn server_timer_is_controlled_by_its_owner() {
+        let service = service();
+        let task = create_task(&service, Some("ann"), "a", "").await;
+        let request = StartPomodoroRequest {
+            task_id: task.id.clone(),
+            ..Default::default()
+        };
+        service
+            .start_pomodoro(request_as("ann", request))
+            .await
+            .unwrap();
+
+        let request = PausePomodoroRequest::default();
+        let error = service
+            .pause_pomodoro(request_as("bob", request))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), tonic::Code::PermissionDenied);
+        let timer = |user| service.get_pomodoro(request_as(user, GetPomodoroRequest::default()));
+        let timer_task =
+            |reply: Response<GetPomodoroReply>| reply.into_inner().timer.unwrap().task_id;
+        assert_eq!(timer_task(timer("ann").await.unwrap()), task.id);
+        assert_eq!(timer_task(timer("bob").await.unwrap()), None);
+        let request = PausePomodoroRequest::default();
+        service
+            .pause_pomodoro(request_as("ann", request))
+            .await
+            .unwrap();
+    }
+}
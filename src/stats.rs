@@ -0,0 +1,195 @@
+use chrono::{Datelike, Duration, FixedOffset, NaiveDate, TimeZone, Utc};
+use prost_types::Timestamp;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use tonic::Status;
+
+use crate::oxydoro::storage::{history_entry, HistoryEntry};
+use crate::oxydoro::{
+    get_stats_request, interruption, pomodoro_session, GetStatsReply, StatsBucket,
+};
+use crate::store::timestamp_key;
+
+/// Aggregates activity recorded in history into days or weeks
+pub struct StatsBuilder {
+    period: get_stats_request::Period,
+    offset: FixedOffset,
+    since: Option<(i64, i32)>,
+    until: Option<(i64, i32)>,
+}
+
+impl StatsBuilder {
+    pub fn new(
+        period: get_stats_request::Period,
+        utc_offset_minutes: i32,
+        since: Option<&Timestamp>,
+        until: Option<&Timestamp>,
+    ) -> Result<StatsBuilder, Status> {
+        let offset = utc_offset_minutes
+            .checked_mul(60)
+            .and_then(FixedOffset::east_opt)
+            .ok_or_else(|| Status::invalid_argument("Invalid UTC offset"))?;
+        Ok(StatsBuilder {
+            period,
+            offset,
+            since: since.map(timestamp_key),
+            until: until.map(timestamp_key),
+        })
+    }
+
+    fn in_range(&self, timestamp: &Timestamp) -> bool {
+        let key = timestamp_key(timestamp);
+        self.since.is_none_or(|since| key >= since) && self.until.is_none_or(|until| key < until)
+    }
+
+    fn local_date(&self, timestamp: &Timestamp) -> Option<NaiveDate> {
+        let time = Utc
+            .timestamp_opt(timestamp.seconds, timestamp.nanos as u32)
+            .single()?;
+        Some(time.with_timezone(&self.offset).naive_local().date())
+    }
+
+    fn period_start(&self, date: NaiveDate) -> NaiveDate {
+        match self.period {
+            get_stats_request::Period::Day => date,
+            get_stats_request::Period::Week => {
+                date - Duration::days(date.weekday().num_days_from_monday() as i64)
+            }
+        }
+    }
+
+    fn bucket<'a>(
+        &self,
+        buckets: &'a mut BTreeMap<NaiveDate, StatsBucket>,
+        date: NaiveDate,
+    ) -> &'a mut StatsBucket {
+        let start = self.period_start(date);
+        buckets.entry(start).or_insert_with(|| StatsBucket {
+            start_date: start.format("%Y-%m-%d").to_string(),
+            ..Default::default()
+        })
+    }
+
+    pub fn build<'a>(
+        &self,
+        history: impl IntoIterator<Item = &'a HistoryEntry>,
+        now: &Timestamp,
+    ) -> GetStatsReply {
+        let mut buckets = BTreeMap::new();
+        // streaks are counted over the whole history
+        let mut productive_days = BTreeSet::new();
+
+        let mut sessions = vec![];
+        // reopened tasks only count again once they are completed again
+        let mut completions = HashMap::new();
+        for entry in history {
+            match &entry.kind {
+                Some(history_entry::Kind::Session(session)) => sessions.push(session),
+                Some(history_entry::Kind::Completion(completion)) => {
+                    let uuid = completion.task_id.as_ref().map(|id| id.uuid.as_str());
+                    match &completion.completed_at {
+                        Some(completed_at) => completions.insert(uuid, completed_at),
+                        None => completions.remove(&uuid),
+                    };
+                }
+                None => (),
+            }
+        }
+
+        for session in sessions {
+            let start = match &session.start {
+                Some(start) => start,
+                None => continue,
+            };
+            let date = match self.local_date(start) {
+                Some(date) => date,
+                None => continue,
+            };
+            let completed = session.outcome() == pomodoro_session::Outcome::Completed;
+            if completed {
+                productive_days.insert(date);
+            }
+            if !self.in_range(start) {
+                continue;
+            }
+            let bucket = self.bucket(&mut buckets, date);
+            for interruption in &session.interruptions {
+                match interruption.kind() {
+                    interruption::Kind::Internal => bucket.internal_interruptions += 1,
+                    interruption::Kind::External => bucket.external_interruptions += 1,
+                }
+            }
+            if completed {
+                bucket.completed_pomodoros += 1;
+                bucket.focused_minutes += (session.planned_duration_ms / 60_000) as u32;
+            } else {
+                bucket.interrupted_pomodoros += 1;
+            }
+        }
+
+        for completed_at in completions.values() {
+            if !self.in_range(completed_at) {
+                continue;
+            }
+            if let Some(date) = self.local_date(completed_at) {
+                self.bucket(&mut buckets, date).tasks_completed += 1;
+            }
+        }
+
+        let (current_streak_days, longest_streak_days) = match self.local_date(now) {
+            Some(today) => streaks(&productive_days, today),
+            None => (0, 0),
+        };
+        GetStatsReply {
+            buckets: buckets.into_values().collect(),
+            current_streak_days,
+            longest_streak_days,
+        }
+    }
+}
+
+/// Current and longest run of consecutive days
+///
+/// Current streak is still alive if there was no activity today yet
+fn streaks(days: &BTreeSet<NaiveDate>, today: NaiveDate) -> (u32, u32) {
+    let mut longest = 0;
+    let mut run = 0;
+    let mut previous: Option<NaiveDate> = None;
+    for day in days {
+        run = match previous {
+            Some(previous) if *day - previous == Duration::days(1) => run + 1,
+            _ => 1,
+        };
+        longest = longest.max(run);
+        previous = Some(*day);
+    }
+
+    let mut current = 0;
+    let mut day = if days.contains(&today) {
+        today
+    } else {
+        today - Duration::days(1)
+    };
+    while days.contains(&day) {
+        current += 1;
+        day -= Duration::days(1);
+    }
+    (current, longest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2020, 3, day).unwrap()
+    }
+
+    #[test]
+    fn streaks_restart_after_a_gap() {
+        let days: BTreeSet<_> = [1, 2, 3, 5, 6].iter().map(|&d| day(d)).collect();
+        assert_eq!(streaks(&days, day(6)), (2, 3));
+        // no activity yet today doesn't break the streak
+        assert_eq!(streaks(&days, day(7)), (2, 3));
+        assert_eq!(streaks(&days, day(8)), (0, 3));
+    }
+}
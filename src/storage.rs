@@ -0,0 +1,267 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use prost::Message;
+
+use crate::oxydoro::storage::{HistoryEntry, StoreSnapshot};
+use crate::oxydoro::PomodoroSession;
+
+const SNAPSHOT_FILE_NAME: &str = "store.pb";
+const HISTORY_FILE_NAME: &str = "history.pb";
+const ACTIVE_SESSION_FILE_NAME: &str = "active_session.pb";
+
+/// Backend that the store loads its state from on startup
+/// and writes it back to after every mutation
+///
+/// History only grows, so it is appended to instead of being part of the snapshot
+pub trait Storage: Send + Sync {
+    fn load(&self) -> io::Result<StoreSnapshot>;
+    fn save(&self, snapshot: &StoreSnapshot) -> io::Result<()>;
+    /// Entries in the order they were appended
+    fn load_history(&self) -> io::Result<Vec<HistoryEntry>>;
+    fn append_history(&self, entry: &HistoryEntry) -> io::Result<()>;
+    fn load_active_session(&self) -> io::Result<Option<PomodoroSession>>;
+    fn save_active_session(&self, session: Option<&PomodoroSession>) -> io::Result<()>;
+}
+
+/// Keeps nothing, state is lost when the server stops
+#[derive(Default)]
+pub struct MemoryStorage;
+
+impl Storage for MemoryStorage {
+    fn load(&self) -> io::Result<StoreSnapshot> {
+        Ok(StoreSnapshot::default())
+    }
+
+    fn save(&self, _: &StoreSnapshot) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn load_history(&self) -> io::Result<Vec<HistoryEntry>> {
+        Ok(vec![])
+    }
+
+    fn append_history(&self, _: &HistoryEntry) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn load_active_session(&self) -> io::Result<Option<PomodoroSession>> {
+        Ok(None)
+    }
+
+    fn save_active_session(&self, _: Option<&PomodoroSession>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Stores protobuf encoded snapshot of the store in a data directory
+///
+/// History is a separate file of length delimited entries
+pub struct FileStorage {
+    path: PathBuf,
+    history_path: PathBuf,
+    active_session_path: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(data_dir: impl Into<PathBuf>) -> io::Result<FileStorage> {
+        let data_dir = data_dir.into();
+        fs::create_dir_all(&data_dir)?;
+        Ok(FileStorage {
+            path: data_dir.join(SNAPSHOT_FILE_NAME),
+            history_path: data_dir.join(HISTORY_FILE_NAME),
+            active_session_path: data_dir.join(ACTIVE_SESSION_FILE_NAME),
+        })
+    }
+}
+
+impl Storage for FileStorage {
+    fn load(&self) -> io::Result<StoreSnapshot> {
+        match fs::read(&self.path) {
+            Ok(buffer) => StoreSnapshot::decode(buffer.as_slice())
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(StoreSnapshot::default()),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn save(&self, snapshot: &StoreSnapshot) -> io::Result<()> {
+        save_message(&self.path, snapshot)
+    }
+
+    fn load_history(&self) -> io::Result<Vec<HistoryEntry>> {
+        let buffer = match fs::read(&self.history_path) {
+            Ok(buffer) => buffer,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(error) => return Err(error),
+        };
+        let mut entries = vec![];
+        let mut remaining = buffer.as_slice();
+        while !remaining.is_empty() {
+            let mut entry = remaining;
+            let length = match prost::encoding::decode_varint(&mut entry) {
+                Ok(length) if length as usize <= entry.len() => length as usize,
+                Err(error) if !is_truncated_varint(remaining) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, error))
+                }
+                // last append was cut short by a crash, later ones have to start after the intact entries
+                _ => {
+                    let intact_length = buffer.len() - remaining.len();
+                    OpenOptions::new()
+                        .write(true)
+                        .open(&self.history_path)?
+                        .set_len(intact_length as u64)?;
+                    break;
+                }
+            };
+            entries.push(
+                HistoryEntry::decode(&entry[..length])
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?,
+            );
+            remaining = &entry[length..];
+        }
+        Ok(entries)
+    }
+
+    fn append_history(&self, entry: &HistoryEntry) -> io::Result<()> {
+        let mut buffer = Vec::with_capacity(entry.encoded_len() + 10);
+        entry
+            .encode_length_delimited(&mut buffer)
+            .map_err(io::Error::other)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.history_path)?;
+        file.write_all(&buffer)?;
+        file.sync_data()
+    }
+
+    fn load_active_session(&self) -> io::Result<Option<PomodoroSession>> {
+        match fs::read(&self.active_session_path) {
+            Ok(buffer) => PomodoroSession::decode(buffer.as_slice())
+                .map(Some)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn save_active_session(&self, session: Option<&PomodoroSession>) -> io::Result<()> {
+        match session {
+            Some(session) => save_message(&self.active_session_path, session),
+            None => match fs::remove_file(&self.active_session_path) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+                _ => Ok(()),
+            },
+        }
+    }
+}
+
+/// Varint that the end of the file cut off, every byte of it announces another one
+fn is_truncated_varint(bytes: &[u8]) -> bool {
+    bytes.len() < 10 && bytes.iter().all(|byte| byte & 0x80 != 0)
+}
+
+/// Write protobuf message to `path`, replacing the previous content at once
+pub fn save_message(path: &Path, message: &impl Message) -> io::Result<()> {
+    let mut buffer = Vec::with_capacity(message.encoded_len());
+    message.encode(&mut buffer).map_err(io::Error::other)?;
+    // write to a temporary file first so that a crash never leaves a half written file
+    let temp_path = path.with_extension("pb.tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(&buffer)?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)?;
+    sync_parent(path)
+}
+
+/// Renames are only durable once the directory holding the file is synced
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> io::Result<()> {
+    match path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        Some(parent) => File::open(parent)?.sync_all(),
+        None => File::open(".")?.sync_all(),
+    }
+}
+
+/// Directories can't be opened as files to sync them on other platforms
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oxydoro::storage::history_entry;
+    use crate::oxydoro::TaskId;
+
+    fn entry(uuid: &str) -> HistoryEntry {
+        HistoryEntry {
+            kind: Some(history_entry::Kind::Session(PomodoroSession {
+                task_id: Some(TaskId {
+                    uuid: uuid.to_owned(),
+                }),
+                ..Default::default()
+            })),
+        }
+    }
+
+    fn encoded(entry: &HistoryEntry) -> Vec<u8> {
+        let mut buffer = vec![];
+        entry.encode_length_delimited(&mut buffer).unwrap();
+        buffer
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("oxydoro-test-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn load_history_drops_append_cut_short() {
+        let data_dir = temp_dir();
+        let storage = FileStorage::new(&data_dir).unwrap();
+        storage.append_history(&entry("a")).unwrap();
+        let intact_length = fs::metadata(&storage.history_path).unwrap().len();
+        let torn = encoded(&entry("b"));
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&storage.history_path)
+            .unwrap();
+        file.write_all(&torn[..torn.len() - 1]).unwrap();
+
+        assert_eq!(storage.load_history().unwrap(), vec![entry("a")]);
+        assert_eq!(
+            fs::metadata(&storage.history_path).unwrap().len(),
+            intact_length
+        );
+        storage.append_history(&entry("c")).unwrap();
+        assert_eq!(
+            storage.load_history().unwrap(),
+            vec![entry("a"), entry("c")]
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn load_history_rejects_corruption_before_the_end() {
+        let data_dir = temp_dir();
+        let storage = FileStorage::new(&data_dir).unwrap();
+        let mut buffer = encoded(&entry("a"));
+        buffer.extend_from_slice(&[0xff; 11]);
+        buffer.extend(encoded(&entry("b")));
+        fs::write(&storage.history_path, &buffer).unwrap();
+
+        let error = storage.load_history().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            fs::metadata(&storage.history_path).unwrap().len(),
+            buffer.len() as u64
+        );
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
@@ -0,0 +1,940 @@
+use prost_types::Timestamp;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::{Mutex, MutexGuard, RwLock};
+use std::time::SystemTime;
+use tokio::sync::broadcast;
+use tonic::Status;
+use uuid::Uuid;
+
+use crate::auth::Caller;
+use crate::config::Limits;
+use crate::oxydoro::storage::{history_entry, HistoryEntry, StoreSnapshot, TaskCompletion};
+use crate::oxydoro::{
+    pomodoro_session, task_event, PomodoroSession, Project, ProjectId, Task, TaskEvent, TaskId,
+    TaskIdList, TaskList, TaskOrder,
+};
+use crate::storage::Storage;
+
+/// Task event together with what decides which subscribers receive it
+///
+/// Only the event itself is sent to clients
+#[derive(Clone, Debug)]
+pub struct RoutedEvent {
+    pub event: TaskEvent,
+    /// Project an updated task belonged to before the change
+    pub previous_project_id: Option<ProjectId>,
+    /// Users allowed to see the changed task, everyone if empty
+    pub visible_to: Vec<String>,
+    /// Users allowed to see an updated task before the change
+    pub previously_visible_to: Vec<String>,
+    /// Tasks of a deleted event in the order of its ids
+    pub deleted: Vec<DeletedTask>,
+}
+
+/// Where a deleted task could be seen before the change
+#[derive(Clone, Debug)]
+pub struct DeletedTask {
+    pub project_id: Option<ProjectId>,
+    pub visible_to: Vec<String>,
+}
+
+impl RoutedEvent {
+    /// Deletion of an updated task for subscribers that no longer get to see it
+    fn deleted_update(&mut self, task: Task) -> task_event::Kind {
+        self.deleted = vec![DeletedTask {
+            project_id: self.previous_project_id.clone(),
+            visible_to: self.previously_visible_to.clone(),
+        }];
+        task_event::Kind::Deleted(TaskIdList {
+            ids: vec![task.id.unwrap_or_default()],
+        })
+    }
+
+    /// Deletion of the tasks matching `keep`, none if there are no such tasks
+    fn retain_deleted<F>(&mut self, list: TaskIdList, keep: F) -> Option<task_event::Kind>
+    where
+        F: Fn(&DeletedTask) -> bool,
+    {
+        let (ids, deleted): (Vec<TaskId>, Vec<DeletedTask>) = list
+            .ids
+            .into_iter()
+            .zip(self.deleted.drain(..))
+            .filter(|(_, task)| keep(task))
+            .unzip();
+        if ids.is_empty() {
+            return None;
+        }
+        self.deleted = deleted;
+        Some(task_event::Kind::Deleted(TaskIdList { ids }))
+    }
+}
+
+impl From<TaskEvent> for RoutedEvent {
+    fn from(event: TaskEvent) -> RoutedEvent {
+        RoutedEvent {
+            event,
+            previous_project_id: None,
+            visible_to: vec![],
+            previously_visible_to: vec![],
+            deleted: vec![],
+        }
+    }
+}
+
+struct State {
+    snapshot: StoreSnapshot,
+    /// Most recent task events ordered by revision
+    changes: VecDeque<RoutedEvent>,
+}
+
+impl State {
+    fn events_since(&self, revision: u64) -> Vec<RoutedEvent> {
+        if revision == 0 {
+            return vec![snapshot_event(&self.snapshot)];
+        }
+        if revision == self.snapshot.revision {
+            return vec![];
+        }
+        let complete = revision < self.snapshot.revision
+            && self
+                .changes
+                .front()
+                .is_some_and(|oldest| oldest.event.revision <= revision + 1);
+        if !complete {
+            return vec![snapshot_event(&self.snapshot)];
+        }
+        self.changes
+            .iter()
+            .filter(|event| event.event.revision > revision)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Server state shared between request handlers and background jobs
+pub struct Store {
+    state: RwLock<State>,
+    storage: Box<dyn Storage>,
+    task_events: broadcast::Sender<RoutedEvent>,
+    /// Number of recent task events kept in memory
+    change_log_capacity: usize,
+    /// Random id of this store, revisions of a restarted server may repeat
+    epoch: String,
+    /// Kept apart from the state so that mutations don't have to copy it
+    history: RwLock<Vec<HistoryEntry>>,
+    /// Work interval of the server timer in progress
+    active_session: Mutex<Option<PomodoroSession>>,
+}
+
+impl Store {
+    /// Load state from storage
+    pub fn new(storage: Box<dyn Storage>, limits: &Limits) -> io::Result<Store> {
+        let mut state = storage.load()?;
+        // tasks stored before versions were introduced
+        for task in state.tasks.iter_mut().filter(|task| task.version == 0) {
+            task.version = 1;
+        }
+        let mut history = storage.load_history()?;
+        if let Some(mut session) = storage.load_active_session()? {
+            // server stopped before the session ended
+            session.end = Some(SystemTime::now().into());
+            session.set_outcome(pomodoro_session::Outcome::Interrupted);
+            let entry = session_entry(session);
+            storage.append_history(&entry)?;
+            history.push(entry);
+            storage.save_active_session(None)?;
+        }
+        let (task_events, _) = broadcast::channel(limits.subscriber_buffer);
+        Ok(Store {
+            state: RwLock::new(State {
+                snapshot: state,
+                changes: VecDeque::new(),
+            }),
+            storage,
+            task_events,
+            change_log_capacity: limits.change_log_capacity,
+            epoch: Uuid::new_v4().to_simple().to_string(),
+            history: RwLock::new(history),
+            active_session: Mutex::new(None),
+        })
+    }
+
+    pub fn read<F, R>(&self, reader: F) -> Result<R, Status>
+    where
+        F: FnOnce(&StoreSnapshot) -> R,
+    {
+        let state = self
+            .state
+            .read()
+            .map_err(|_| Status::internal("Failed to unlock store"))?;
+        Ok(reader(&state.snapshot))
+    }
+
+    pub fn epoch(&self) -> &str {
+        &self.epoch
+    }
+
+    /// Subscribe to changes of the task list
+    ///
+    /// Returns events after `since_revision` of `epoch`, or a snapshot if it is 0, from
+    /// another epoch or some of the events are no longer kept, and a receiver of every
+    /// event that follows them
+    pub fn subscribe_to_tasks(
+        &self,
+        since_revision: u64,
+        epoch: &str,
+    ) -> Result<(Vec<RoutedEvent>, broadcast::Receiver<RoutedEvent>), Status> {
+        // events are sent under the write lock so none can slip in between
+        let state = self
+            .state
+            .read()
+            .map_err(|_| Status::internal("Failed to unlock store"))?;
+        let since_revision = if epoch == self.epoch {
+            since_revision
+        } else {
+            0
+        };
+        Ok((
+            state.events_since(since_revision),
+            self.task_events.subscribe(),
+        ))
+    }
+
+    pub fn read_history<F, R>(&self, reader: F) -> Result<R, Status>
+    where
+        F: FnOnce(&[HistoryEntry]) -> R,
+    {
+        let history = self
+            .history
+            .read()
+            .map_err(|_| Status::internal("Failed to unlock history"))?;
+        Ok(reader(&history))
+    }
+
+    fn append_history(&self, entry: HistoryEntry) -> Result<(), Status> {
+        let mut history = self
+            .history
+            .write()
+            .map_err(|_| Status::internal("Failed to unlock history"))?;
+        self.storage
+            .append_history(&entry)
+            .map_err(|_| Status::internal("Failed to persist history"))?;
+        history.push(entry);
+        Ok(())
+    }
+
+    /// Apply change to the state, persist it and notify subscribers
+    ///
+    /// The state is left untouched if the change fails or can't be persisted
+    pub fn mutate<F, R>(&self, change: F) -> Result<R, Status>
+    where
+        F: FnOnce(&mut StoreSnapshot) -> Result<R, Status>,
+    {
+        let mut state = self
+            .state
+            .write()
+            .map_err(|_| Status::internal("Failed to unlock store"))?;
+        let original = state.snapshot.clone();
+        let result = change(&mut state.snapshot).and_then(|result| {
+            check_hierarchy(&state.snapshot.tasks)?;
+            update_progress(&mut state.snapshot.tasks);
+            bump_versions(&original.tasks, &mut state.snapshot.tasks);
+            let mut events = task_events(&original.tasks, &state.snapshot.tasks);
+            for event in &mut events {
+                state.snapshot.revision += 1;
+                event.event.revision = state.snapshot.revision;
+            }
+            self.storage
+                .save(&state.snapshot)
+                .map_err(|_| Status::internal("Failed to persist store"))?;
+            Ok((result, events))
+        });
+        match result {
+            Ok((result, events)) => {
+                state.changes.extend(events.iter().cloned());
+                while state.changes.len() > self.change_log_capacity {
+                    state.changes.pop_front();
+                }
+                for event in events {
+                    // no subscribers is not an error
+                    let _ = self.task_events.send(event);
+                }
+                Ok(result)
+            }
+            Err(error) => {
+                state.snapshot = original;
+                Err(error)
+            }
+        }
+    }
+
+    /// Event as seen by the caller, tasks it is not allowed to see are left out
+    ///
+    /// Tasks becoming visible or invisible to the caller appear as created or deleted
+    pub fn scope_task_event(&self, mut event: RoutedEvent, caller: &Caller) -> Option<RoutedEvent> {
+        if caller.is_anonymous() {
+            return Some(event);
+        }
+        let kind = match event.event.kind.take()? {
+            task_event::Kind::Snapshot(list) => task_event::Kind::Snapshot(TaskList {
+                tasks: list
+                    .tasks
+                    .into_iter()
+                    .filter(|task| caller.can_see(task))
+                    .collect(),
+            }),
+            task_event::Kind::Updated(task) => {
+                match (
+                    caller.is_allowed(&event.previously_visible_to),
+                    caller.is_allowed(&event.visible_to),
+                ) {
+                    (true, true) => task_event::Kind::Updated(task),
+                    (false, true) => task_event::Kind::Created(task),
+                    (true, false) => event.deleted_update(task),
+                    (false, false) => return None,
+                }
+            }
+            task_event::Kind::Deleted(list) => {
+                event.retain_deleted(list, |task| caller.is_allowed(&task.visible_to))?
+            }
+            task_event::Kind::Reordered(order) => {
+                // visibility is not part of the event, so it is taken from the current state
+                let ids = self
+                    .read(|snapshot| {
+                        snapshot
+                            .tasks
+                            .iter()
+                            .filter(|task| caller.can_see(task))
+                            .filter_map(|task| task.id.clone())
+                            .collect::<Vec<_>>()
+                    })
+                    .ok()?;
+                task_event::Kind::Reordered(TaskOrder {
+                    ids: order
+                        .ids
+                        .into_iter()
+                        .filter(|id| ids.contains(id))
+                        .collect(),
+                })
+            }
+            kind if caller.is_allowed(&event.visible_to) => kind,
+            _ => return None,
+        };
+        event.event.kind = Some(kind);
+        Some(event)
+    }
+
+    /// Apply change to a single task, persist it and notify subscribers
+    pub fn modify_task<F>(
+        &self,
+        caller: &Caller,
+        id: Option<TaskId>,
+        expected_version: u64,
+        update: F,
+    ) -> Result<Task, Status>
+    where
+        F: FnOnce(&mut Task),
+    {
+        let id = id.ok_or_else(|| Status::invalid_argument("Missing task id"))?;
+        self.mutate(|state| {
+            let index = find_task_index(&state.tasks, &id)?;
+            let task = &mut state.tasks[index];
+            caller.check_visible(task)?;
+            check_version(task, expected_version)?;
+            let previous = task.clone();
+            update(task);
+            // bumped here as well so that the returned task carries the new version
+            bump_versions(std::slice::from_ref(&previous), std::slice::from_mut(task));
+            let task = task.clone();
+            if task.parent_id != previous.parent_id {
+                if let Some(parent_id) = &task.parent_id {
+                    caller
+                        .check_visible(&state.tasks[find_task_index(&state.tasks, parent_id)?])?;
+                }
+            }
+            if task.project_id != previous.project_id {
+                if let Some(project_id) = &task.project_id {
+                    find_project_index(&state.projects, project_id)?;
+                }
+                // moved tasks are appended to their new project together with their subtasks
+                let mut moved = vec![id.clone()];
+                moved.extend(descendants(&state.tasks, &id));
+                for moved_id in moved {
+                    let index = find_task_index(&state.tasks, &moved_id)?;
+                    let mut moved_task = state.tasks.remove(index);
+                    moved_task.project_id = task.project_id.clone();
+                    state.tasks.push(moved_task);
+                }
+            }
+            Ok(task)
+        })
+    }
+
+    /// Add completing or reopening the task by `user` to history
+    pub fn record_completion(&self, task: &Task, user: &str) -> Result<(), Status> {
+        self.append_history(completion_entry(task, user))
+    }
+
+    fn lock_active_session(&self) -> Result<MutexGuard<'_, Option<PomodoroSession>>, Status> {
+        self.active_session
+            .lock()
+            .map_err(|_| Status::internal("Failed to unlock active session"))
+    }
+
+    /// Remember session in progress so that it can be recorded as interrupted after a restart
+    pub fn save_active_session(&self, session: PomodoroSession) -> Result<(), Status> {
+        let mut active_session = self.lock_active_session()?;
+        self.storage
+            .save_active_session(Some(&session))
+            .map_err(|_| Status::internal("Failed to persist active session"))?;
+        *active_session = Some(session);
+        Ok(())
+    }
+
+    /// Add finished session to history
+    ///
+    /// Completed sessions are counted towards their task
+    pub fn end_session(&self, session: PomodoroSession) -> Result<(), Status> {
+        let completed_task_id = match session.outcome() {
+            pomodoro_session::Outcome::Completed => session.task_id.clone(),
+            _ => None,
+        };
+        let mut active_session = self.lock_active_session()?;
+        // sessions of room timers are never the active one
+        let was_active = active_session.as_ref().is_some_and(|active| {
+            active.start == session.start && active.task_id == session.task_id
+        });
+        // appended first so that a crash records the session twice rather than not at all
+        self.append_history(session_entry(session))?;
+        if was_active {
+            self.storage
+                .save_active_session(None)
+                .map_err(|_| Status::internal("Failed to persist active session"))?;
+            *active_session = None;
+        }
+        drop(active_session);
+        if let Some(task_id) = completed_task_id {
+            self.mutate(|state| {
+                // task might have been deleted in the meantime
+                if let Ok(task) = find_task_mut(&mut state.tasks, &task_id) {
+                    task.completed_pomodoros += 1;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+}
+
+fn session_entry(session: PomodoroSession) -> HistoryEntry {
+    HistoryEntry {
+        kind: Some(history_entry::Kind::Session(session)),
+    }
+}
+
+fn completion_entry(task: &Task, user: &str) -> HistoryEntry {
+    HistoryEntry {
+        kind: Some(history_entry::Kind::Completion(TaskCompletion {
+            task_id: task.id.clone(),
+            completed_at: task.completed_at.clone().filter(|_| task.done),
+            user: user.to_owned(),
+        })),
+    }
+}
+
+/// Every parent has to exist, be in the same project and can't be its own ancestor
+fn check_hierarchy(tasks: &[Task]) -> Result<(), Status> {
+    let by_id: HashMap<&str, &Task> = tasks
+        .iter()
+        .filter_map(|task| Some((task.id.as_ref()?.uuid.as_str(), task)))
+        .collect();
+    for task in tasks {
+        let mut ancestor = task;
+        // longer chains can only be cycles
+        for _ in 0..tasks.len() {
+            let parent_id = match &ancestor.parent_id {
+                Some(parent_id) => parent_id,
+                None => break,
+            };
+            let parent = by_id
+                .get(parent_id.uuid.as_str())
+                .ok_or_else(|| Status::not_found(format!("Task {} not found", parent_id.uuid)))?;
+            if parent.project_id != ancestor.project_id {
+                return Err(Status::invalid_argument(
+                    "Subtasks have to be in the project of their parent",
+                ));
+            }
+            if parent.id == task.id {
+                return Err(Status::invalid_argument(
+                    "Task can't be a subtask of itself or its subtasks",
+                ));
+            }
+            ancestor = parent;
+        }
+    }
+    Ok(())
+}
+
+fn update_progress(tasks: &mut [Task]) {
+    let mut progress: HashMap<String, (u32, u32)> = HashMap::new();
+    for task in tasks.iter() {
+        if let Some(parent_id) = &task.parent_id {
+            let (count, completed) = progress.entry(parent_id.uuid.clone()).or_default();
+            *count += 1;
+            if task.done {
+                *completed += 1;
+            }
+        }
+    }
+    for task in tasks {
+        let uuid = task
+            .id
+            .as_ref()
+            .map(|id| id.uuid.as_str())
+            .unwrap_or_default();
+        let (count, completed) = progress.get(uuid).copied().unwrap_or_default();
+        task.subtask_count = count;
+        task.completed_subtask_count = completed;
+    }
+}
+
+/// Subtasks of the task, their subtasks and so on
+pub fn descendants(tasks: &[Task], id: &TaskId) -> Vec<TaskId> {
+    let mut found = vec![];
+    let mut parents = vec![id.clone()];
+    while let Some(parent) = parents.pop() {
+        for task in tasks {
+            if task.parent_id.as_ref() == Some(&parent) {
+                // guards against cycles in changes that were not checked yet
+                if let Some(child) = task.id.as_ref().filter(|child| !found.contains(*child)) {
+                    found.push(child.clone());
+                    parents.push(child.clone());
+                }
+            }
+        }
+    }
+    found
+}
+
+/// Set versions of new tasks and bump versions of changed ones
+fn bump_versions(before: &[Task], after: &mut [Task]) {
+    let before_by_id: HashMap<&str, &Task> = before
+        .iter()
+        .filter_map(|task| Some((task.id.as_ref()?.uuid.as_str(), task)))
+        .collect();
+    for task in after {
+        let previous = task
+            .id
+            .as_ref()
+            .and_then(|id| before_by_id.get(id.uuid.as_str()));
+        match previous {
+            Some(previous) if *previous != task => task.version = previous.version + 1,
+            Some(_) => (),
+            None => task.version = 1,
+        }
+    }
+}
+
+fn snapshot_event(state: &StoreSnapshot) -> RoutedEvent {
+    RoutedEvent::from(TaskEvent {
+        revision: state.revision,
+        kind: Some(task_event::Kind::Snapshot(TaskList {
+            tasks: state.tasks.clone(),
+        })),
+        ..Default::default()
+    })
+}
+
+/// Events turning `before` into `after`, revisions are left unset
+fn task_events(before: &[Task], after: &[Task]) -> Vec<RoutedEvent> {
+    let uuid = |task: &Task| {
+        task.id
+            .as_ref()
+            .map(|id| id.uuid.clone())
+            .unwrap_or_default()
+    };
+    let before_by_id: HashMap<String, &Task> =
+        before.iter().map(|task| (uuid(task), task)).collect();
+    let after_by_id: HashMap<String, &Task> = after.iter().map(|task| (uuid(task), task)).collect();
+
+    let mut events = vec![];
+    // order the list ends up in if only the events below are applied
+    let mut order = vec![];
+    let mut deleted = vec![];
+    for task in before {
+        if after_by_id.contains_key(&uuid(task)) {
+            order.push(uuid(task));
+        } else {
+            deleted.push(task);
+        }
+    }
+    // tasks removed together are reported together
+    if !deleted.is_empty() {
+        events.push(deleted_event(&deleted));
+    }
+    for task in after {
+        match before_by_id.get(&uuid(task)) {
+            None => {
+                let kind = task_event::Kind::Created(task.clone());
+                events.push(task_event(kind, task, None));
+                order.push(uuid(task));
+            }
+            Some(previous) if *previous != task => {
+                let kind = task_event::Kind::Updated(task.clone());
+                events.push(task_event(kind, task, Some(previous)));
+            }
+            Some(_) => (),
+        }
+    }
+
+    // order only matters within a project
+    let mut projects: Vec<&Option<ProjectId>> = vec![];
+    for task in after {
+        if !projects.contains(&&task.project_id) {
+            projects.push(&task.project_id);
+        }
+    }
+    for project_id in projects {
+        let tasks: Vec<&Task> = after
+            .iter()
+            .filter(|task| task.project_id == *project_id)
+            .collect();
+        let expected = order
+            .iter()
+            .filter(|id| after_by_id[id.as_str()].project_id == *project_id);
+        if !expected.eq(tasks
+            .iter()
+            .map(|task| uuid(task))
+            .collect::<Vec<_>>()
+            .iter())
+        {
+            let kind = task_event::Kind::Reordered(TaskOrder {
+                ids: tasks.iter().filter_map(|task| task.id.clone()).collect(),
+            });
+            events.push(RoutedEvent::from(TaskEvent {
+                kind: Some(kind),
+                project_id: project_id.clone(),
+                ..Default::default()
+            }));
+        }
+    }
+    events
+}
+
+/// Event about removing `tasks`, the project is only set if they all share it
+fn deleted_event(tasks: &[&Task]) -> RoutedEvent {
+    let project_id = tasks[0].project_id.clone();
+    let shared_project = tasks.iter().all(|task| task.project_id == project_id);
+    let mut event = RoutedEvent::from(TaskEvent {
+        kind: Some(task_event::Kind::Deleted(TaskIdList {
+            ids: tasks
+                .iter()
+                .map(|task| task.id.clone().unwrap_or_default())
+                .collect(),
+        })),
+        project_id: project_id.filter(|_| shared_project),
+        ..Default::default()
+    });
+    event.deleted = tasks
+        .iter()
+        .map(|task| DeletedTask {
+            project_id: task.project_id.clone(),
+            visible_to: visible_to(task),
+        })
+        .collect();
+    event
+}
+
+/// Event about `task`, `previous` is its state before an update
+fn task_event(kind: task_event::Kind, task: &Task, previous: Option<&Task>) -> RoutedEvent {
+    RoutedEvent {
+        event: TaskEvent {
+            revision: 0,
+            kind: Some(kind),
+            project_id: task.project_id.clone(),
+        },
+        previous_project_id: previous.and_then(|previous| previous.project_id.clone()),
+        visible_to: visible_to(task),
+        previously_visible_to: previous.map(visible_to).unwrap_or_default(),
+        deleted: vec![],
+    }
+}
+
+/// Users allowed to see the task, everyone if empty
+pub fn visible_to(task: &Task) -> Vec<String> {
+    if task.creator.is_empty() {
+        return vec![];
+    }
+    let mut users = vec![task.creator.clone()];
+    if !task.assignee.is_empty() && task.assignee != task.creator {
+        users.push(task.assignee.clone());
+    }
+    users
+}
+
+/// Event as seen by a subscriber to a single project
+///
+/// Tasks moved in or out of the project appear as created or deleted
+pub fn scope_task_event(mut event: RoutedEvent, project_id: &ProjectId) -> Option<RoutedEvent> {
+    let in_project = |id: &Option<ProjectId>| id.as_ref() == Some(project_id);
+    let kind = match event.event.kind.take()? {
+        task_event::Kind::Snapshot(list) => task_event::Kind::Snapshot(TaskList {
+            tasks: list
+                .tasks
+                .into_iter()
+                .filter(|task| in_project(&task.project_id))
+                .collect(),
+        }),
+        task_event::Kind::Updated(task) => {
+            match (
+                in_project(&event.previous_project_id),
+                in_project(&event.event.project_id),
+            ) {
+                (true, true) => task_event::Kind::Updated(task),
+                (false, true) => task_event::Kind::Created(task),
+                (true, false) => event.deleted_update(task),
+                (false, false) => return None,
+            }
+        }
+        task_event::Kind::Deleted(list) => {
+            let kind = event.retain_deleted(list, |task| in_project(&task.project_id))?;
+            event.event.project_id = Some(project_id.clone());
+            kind
+        }
+        kind if in_project(&event.event.project_id) => kind,
+        _ => return None,
+    };
+    event.event.kind = Some(kind);
+    Some(event)
+}
+
+/// Timestamps ordered chronologically
+pub fn timestamp_key(timestamp: &Timestamp) -> (i64, i32) {
+    (timestamp.seconds, timestamp.nanos)
+}
+
+/// Expected version 0 matches any version
+pub fn check_version(task: &Task, expected_version: u64) -> Result<(), Status> {
+    if expected_version != 0 && task.version != expected_version {
+        return Err(Status::failed_precondition(format!(
+            "Task {} is at version {}, expected {}",
+            task.id
+                .as_ref()
+                .map(|id| id.uuid.as_str())
+                .unwrap_or_default(),
+            task.version,
+            expected_version
+        )));
+    }
+    Ok(())
+}
+
+pub fn find_task_index(tasks: &[Task], id: &TaskId) -> Result<usize, Status> {
+    tasks
+        .iter()
+        .position(|task| task.id.as_ref() == Some(id))
+        .ok_or_else(|| Status::not_found(format!("Task {} not found", id.uuid)))
+}
+
+pub fn find_project_index(projects: &[Project], id: &ProjectId) -> Result<usize, Status> {
+    projects
+        .iter()
+        .position(|project| project.id.as_ref() == Some(id))
+        .ok_or_else(|| Status::not_found(format!("Project {} not found", id.uuid)))
+}
+
+pub fn find_task_mut<'a>(tasks: &'a mut [Task], id: &TaskId) -> Result<&'a mut Task, Status> {
+    let index = find_task_index(tasks, id)?;
+    Ok(&mut tasks[index])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn task(uuid: &str) -> Task {
+        Task {
+            id: Some(TaskId {
+                uuid: uuid.to_owned(),
+            }),
+            title: uuid.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn kinds(events: Vec<RoutedEvent>) -> Vec<task_event::Kind> {
+        events
+            .into_iter()
+            .filter_map(|event| event.event.kind)
+            .collect()
+    }
+
+    #[test]
+    fn task_events_report_deleted_and_created_tasks() {
+        let before = vec![task("a"), task("b"), task("c")];
+        let after = vec![task("b"), task("d")];
+        assert_eq!(
+            kinds(task_events(&before, &after)),
+            vec![
+                task_event::Kind::Deleted(TaskIdList {
+                    ids: vec![task("a").id.unwrap(), task("c").id.unwrap()],
+                }),
+                task_event::Kind::Created(task("d")),
+            ]
+        );
+    }
+
+    #[test]
+    fn task_events_report_updated_task() {
+        let before = vec![task("a"), task("b")];
+        let mut renamed = task("b");
+        renamed.title = "renamed".to_owned();
+        let after = vec![task("a"), renamed.clone()];
+        assert_eq!(
+            kinds(task_events(&before, &after)),
+            vec![task_event::Kind::Updated(renamed)]
+        );
+    }
+
+    #[test]
+    fn task_events_report_previous_project_of_moved_task() {
+        let project_id = ProjectId {
+            uuid: "p".to_owned(),
+        };
+        let before = vec![task("a")];
+        let mut moved = task("a");
+        moved.project_id = Some(project_id.clone());
+        let events = task_events(&before, std::slice::from_ref(&moved));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event.kind, Some(task_event::Kind::Updated(moved)));
+        assert_eq!(events[0].event.project_id, Some(project_id));
+        assert_eq!(events[0].previous_project_id, None);
+    }
+
+    #[test]
+    fn scope_task_event_keeps_deleted_tasks_of_the_project() {
+        let project_id = ProjectId {
+            uuid: "p".to_owned(),
+        };
+        let mut in_project = task("a");
+        in_project.project_id = Some(project_id.clone());
+        let mut events = task_events(&[in_project.clone(), task("b")], &[]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event.project_id, None);
+
+        let event = scope_task_event(events.pop().unwrap(), &project_id).unwrap();
+        assert_eq!(
+            event.event.kind,
+            Some(task_event::Kind::Deleted(TaskIdList {
+                ids: vec![in_project.id.unwrap()],
+            }))
+        );
+        assert_eq!(event.event.project_id, Some(project_id));
+    }
+
+    #[test]
+    fn task_events_report_new_order() {
+        let before = vec![task("a"), task("b"), task("c")];
+        let after = vec![task("c"), task("a"), task("b")];
+        assert_eq!(
+            kinds(task_events(&before, &after)),
+            vec![task_event::Kind::Reordered(TaskOrder {
+                ids: after.iter().filter_map(|task| task.id.clone()).collect(),
+            })]
+        );
+    }
+
+    #[test]
+    fn events_since_falls_back_to_snapshot_once_log_is_compacted() {
+        let changes = (8..=10)
+            .map(|revision| {
+                RoutedEvent::from(TaskEvent {
+                    revision,
+                    kind: Some(task_event::Kind::Created(task(&revision.to_string()))),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let state = State {
+            snapshot: StoreSnapshot {
+                revision: 10,
+                ..Default::default()
+            },
+            changes,
+        };
+        let revisions = |since| {
+            state
+                .events_since(since)
+                .into_iter()
+                .map(|event| event.event.revision)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(revisions(7), vec![8, 9, 10]);
+        assert_eq!(revisions(9), vec![10]);
+        assert_eq!(revisions(10), Vec::<u64>::new());
+        // revision 6 is no longer in the log
+        let events = state.events_since(5);
+        assert_eq!(events.len(), 1);
+        assert!(matches!(
+            events[0].event.kind,
+            Some(task_event::Kind::Snapshot(_))
+        ));
+    }
+
+    #[test]
+    fn check_version_rejects_stale_versions() {
+        let mut a = task("a");
+        a.version = 3;
+        assert!(check_version(&a, 3).is_ok());
+        // version 0 skips the check
+        assert!(check_version(&a, 0).is_ok());
+        let error = check_version(&a, 2).unwrap_err();
+        assert_eq!(error.code(), tonic::Code::FailedPrecondition);
+    }
+
+    #[test]
+    fn check_hierarchy_rejects_cycles() {
+        let mut a = task("a");
+        let mut b = task("b");
+        a.parent_id = b.id.clone();
+        assert!(check_hierarchy(&[a.clone(), b.clone()]).is_ok());
+        b.parent_id = a.id.clone();
+        let error = check_hierarchy(&[a, b]).unwrap_err();
+        assert_eq!(error.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn scope_task_event_turns_visibility_changes_into_created_and_deleted() {
+        let store = Store::new(Box::new(MemoryStorage), &Limits::default()).unwrap();
+        let mut private = task("a");
+        private.creator = "ann".to_owned();
+        let mut assigned = private.clone();
+        assigned.assignee = "bob".to_owned();
+        let scoped = |before: &Task, after: &Task, user: &str| {
+            let event = task_events(std::slice::from_ref(before), std::slice::from_ref(after))
+                .pop()
+                .unwrap();
+            store
+                .scope_task_event(event, &Caller::user(user))
+                .and_then(|event| event.event.kind)
+        };
+
+        assert_eq!(
+            scoped(&private, &assigned, "bob"),
+            Some(task_event::Kind::Created(assigned.clone()))
+        );
+        assert_eq!(
+            scoped(&assigned, &private, "bob"),
+            Some(task_event::Kind::Deleted(TaskIdList {
+                ids: vec![private.id.clone().unwrap()],
+            }))
+        );
+        assert_eq!(
+            scoped(&private, &assigned, "ann"),
+            Some(task_event::Kind::Updated(assigned.clone()))
+        );
+        assert_eq!(scoped(&private, &assigned, "carol"), None);
+    }
+}
@@ -1,4 +1,4 @@
-use iced::{button, container, scrollable, text_input};
+use iced::{button, checkbox, container, scrollable, text_input};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Theme {
@@ -43,6 +43,15 @@ impl From<Theme> for Box<dyn button::StyleSheet> {
     }
 }
 
+impl From<Theme> for Box<dyn checkbox::StyleSheet> {
+    fn from(theme: Theme) -> Self {
+        match theme {
+            Theme::Light => Default::default(),
+            Theme::Dark => dark::Checkbox.into(),
+        }
+    }
+}
+
 impl From<Theme> for Box<dyn scrollable::StyleSheet> {
     fn from(theme: Theme) -> Self {
         match theme {
@@ -79,7 +88,7 @@ mod light {
 }
 
 mod dark {
-    use iced::{button, container, scrollable, text_input, Background, Color};
+    use iced::{button, checkbox, container, scrollable, text_input, Background, Color};
 
     const SURFACE: Color = Color::from_rgb(
         0x40 as f32 / 255.0,
@@ -187,6 +196,30 @@ mod dark {
         }
     }
 
+    pub struct Checkbox;
+
+    impl checkbox::StyleSheet for Checkbox {
+        fn active(&self, is_checked: bool) -> checkbox::Style {
+            checkbox::Style {
+                background: Background::Color(if is_checked { ACTIVE } else { SURFACE }),
+                checkmark_color: Color::WHITE,
+                border_radius: 2,
+                border_width: 1,
+                border_color: ACTIVE,
+            }
+        }
+
+        fn hovered(&self, is_checked: bool) -> checkbox::Style {
+            checkbox::Style {
+                background: Background::Color(Color {
+                    a: 0.8,
+                    ..if is_checked { ACTIVE } else { SURFACE }
+                }),
+                ..self.active(is_checked)
+            }
+        }
+    }
+
     pub struct Scrollable;
 
     impl scrollable::StyleSheet for Scrollable {